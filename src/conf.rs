@@ -72,7 +72,7 @@ impl Conf {
                 );
             } else {
                 info!("Creating dotenv file {}.", env_path.display());
-                fs::write(&env_path, "")?;
+                fs::write(env_path, "")?;
             }
         }
 
@@ -163,59 +163,267 @@ pub struct FileConf {
 #[cfg(feature = "google_calendar")]
 mod google_calendar {
     use serde::{Deserialize, Serialize};
-    use std::{env, path::PathBuf};
+    use std::{collections::BTreeMap, env, path::PathBuf};
 
     use super::user_cache_path;
 
-    #[derive(Serialize, Deserialize)]
+    #[derive(Clone, Serialize, Deserialize)]
     #[serde(default)]
     pub struct GoogleCalendarGlobalConf {
         pub client_id: String,
         pub client_secret: String,
         pub token_dir: PathBuf,
         pub token_filename: String,
+        /// Whether to encrypt the token file with a passphrase
+        pub token_encryption: bool,
+        /// Commands storing the token instead of the token file
+        pub token_command: Option<TokenCommand>,
+        pub state_filename: String,
         pub redirect_uri: String,
+        pub auth_flow: AuthFlow,
+        /// Path to a service account key, used instead of authorizing
+        pub service_account_key: Option<PathBuf>,
+        /// Number of retries of requests which were rate limited or failed
+        /// on the server
+        pub max_retries: u32,
+        pub time_zone: Option<String>,
+    }
+
+    /// Shell commands loading and storing the token, e.g. with `pass`
+    #[derive(Clone, Serialize, Deserialize)]
+    pub struct TokenCommand {
+        /// Prints the token
+        pub get: String,
+        /// Reads the token from stdin
+        pub store: String,
+    }
+
+    /// How to authorize access when no token is stored
+    #[derive(Clone, Copy, Serialize, Deserialize)]
+    #[serde(rename_all = "lowercase")]
+    pub enum AuthFlow {
+        /// Authorizes in a browser, redirecting to a loopback address
+        Browser,
+        /// Authorizes on another device by entering a user code
+        Device,
     }
 
     impl Default for GoogleCalendarGlobalConf {
         fn default() -> Self {
             GoogleCalendarGlobalConf {
-                client_id: env::var("GOOGLE_CLIENT_ID").unwrap(),
-                client_secret: env::var("GOOGLE_CLIENT_SECRET").unwrap(),
+                client_id: env::var("GOOGLE_CLIENT_ID").unwrap_or_default(),
+                client_secret: env::var("GOOGLE_CLIENT_SECRET").unwrap_or_default(),
                 token_dir: user_cache_path(),
                 token_filename: "google-token.json".into(),
+                token_encryption: false,
+                token_command: None,
+                state_filename: "google-state.json".into(),
                 redirect_uri: "http://localhost".into(),
+                auth_flow: AuthFlow::Browser,
+                service_account_key: None,
+                max_retries: 5,
+                time_zone: None,
             }
         }
     }
 
-    #[derive(Serialize, Deserialize)]
+    #[derive(Clone, Serialize, Deserialize)]
     #[serde(default)]
     pub struct GoogleCalendarConf {
+        /// Id or name of the calendar
         pub calendar: String,
+        /// Other calendars synced with the same file
+        pub calendars: Vec<CalendarConf>,
         pub append_new: bool,
         pub append_headline: String,
         pub property: String,
+        /// Property holding the id of the calendar owning a headline
+        pub calendar_property: String,
         pub up_days: u8,
         pub down_days: u8,
+        pub conflict: ConflictPolicy,
+        pub on_delete: DeletePolicy,
+        pub max_deletions: usize,
+        pub send_updates: SendUpdates,
+        pub time_zone: Option<String>,
+        pub fields: FieldsConf,
+        pub colors: ColorsConf,
+    }
+
+    /// A calendar synced with an org file
+    #[derive(Clone, Serialize, Deserialize)]
+    pub struct CalendarConf {
+        /// Id or name of the calendar
+        pub calendar: String,
+        /// Headline new events are appended to, instead of the one of the file
+        #[serde(default)]
+        pub append_headline: Option<String>,
+        /// Tag of the headlines belonging to this calendar
+        #[serde(default)]
+        pub tag: Option<String>,
+        #[serde(default)]
+        pub direction: SyncDirection,
+    }
+
+    /// Which side is updated when synchronizing a calendar
+    #[derive(Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+    #[serde(rename_all = "lowercase")]
+    pub enum SyncDirection {
+        #[default]
+        Both,
+        /// Only updates the calendar from org
+        Push,
+        /// Only updates org from the calendar
+        Pull,
+    }
+
+    impl SyncDirection {
+        /// Returns `true` if changes in org are sent to the calendar
+        pub fn pushes(self) -> bool {
+            self != SyncDirection::Pull
+        }
+
+        /// Returns `true` if changes in the calendar are written to org
+        pub fn pulls(self) -> bool {
+            self != SyncDirection::Push
+        }
+    }
+
+    impl GoogleCalendarConf {
+        /// Returns all calendars synced with the file, starting with `calendar`
+        pub fn calendars(&self) -> Vec<CalendarConf> {
+            let main = Some(CalendarConf {
+                calendar: self.calendar.clone(),
+                append_headline: None,
+                tag: None,
+                direction: SyncDirection::Both,
+            })
+            .filter(|calendar| !calendar.calendar.is_empty());
+
+            main.into_iter().chain(self.calendars.clone()).collect()
+        }
+    }
+
+    /// How headline fields are mapped to event fields
+    #[derive(Clone, Serialize, Deserialize)]
+    #[serde(default)]
+    pub struct FieldsConf {
+        /// Whether to sync the section of the headline with the event description
+        pub description: bool,
+        /// Property synced with the event location, if any
+        pub location: Option<String>,
+        /// Where to store the tags of the headline
+        pub tags: TagsMapping,
+        /// Property synced with the event reminders, if any
+        pub reminders: Option<String>,
+        /// Property synced with the event attendees, if any
+        pub attendees: Option<String>,
+    }
+
+    /// Where to store the tags of a headline in its event
+    #[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+    #[serde(rename_all = "lowercase")]
+    pub enum TagsMapping {
+        /// Doesn't sync tags
+        None,
+        /// Appends a line of tags to the description
+        Description,
+        /// Stores the tags in a private extended property
+        Extended,
+    }
+
+    /// Event colors of tags and priorities
+    #[derive(Clone, Default, Serialize, Deserialize)]
+    #[serde(default)]
+    pub struct ColorsConf {
+        /// Color id by tag, taking precedence over priorities
+        pub tags: BTreeMap<String, String>,
+        /// Color id by priority
+        pub priorities: BTreeMap<char, String>,
+    }
+
+    impl Default for FieldsConf {
+        fn default() -> Self {
+            FieldsConf {
+                description: true,
+                location: Some("LOCATION".into()),
+                tags: TagsMapping::Extended,
+                reminders: Some("REMINDERS".into()),
+                attendees: Some("ATTENDEES".into()),
+            }
+        }
+    }
+
+    /// Which side wins when a headline and its event were both changed
+    #[derive(Clone, Copy, Serialize, Deserialize)]
+    #[serde(rename_all = "lowercase")]
+    pub enum ConflictPolicy {
+        Org,
+        Calendar,
+        Ask,
+    }
+
+    /// Which attendees are notified when an event is created, changed or deleted
+    #[derive(Clone, Copy, Serialize, Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    pub enum SendUpdates {
+        All,
+        /// Notifies only attendees without a Google Calendar account
+        ExternalOnly,
+        None,
+    }
+
+    impl SendUpdates {
+        pub fn as_str(self) -> &'static str {
+            match self {
+                SendUpdates::All => "all",
+                SendUpdates::ExternalOnly => "externalOnly",
+                SendUpdates::None => "none",
+            }
+        }
+    }
+
+    /// What to do with a headline whose event was deleted from the calendar
+    #[derive(Clone, Serialize, Deserialize)]
+    #[serde(rename_all = "lowercase")]
+    pub enum DeletePolicy {
+        /// Removes the headline
+        Remove,
+        /// Sets the todo keyword of the headline
+        Keyword(String),
+        /// Adds a tag to the headline
+        Tag(String),
     }
 
     impl Default for GoogleCalendarConf {
         fn default() -> Self {
             GoogleCalendarConf {
                 calendar: String::new(),
+                calendars: Vec::new(),
                 append_new: false,
                 append_headline: "Sync".into(),
                 property: "EVENT_ID".into(),
+                calendar_property: "CALENDAR_ID".into(),
                 up_days: 7,
                 down_days: 7,
+                conflict: ConflictPolicy::Org,
+                on_delete: DeletePolicy::Keyword("DONE".into()),
+                max_deletions: 10,
+                send_updates: SendUpdates::None,
+                time_zone: None,
+                fields: FieldsConf::default(),
+                colors: ColorsConf::default(),
             }
         }
     }
 }
 
 #[cfg(feature = "google_calendar")]
-pub use google_calendar::{GoogleCalendarConf, GoogleCalendarGlobalConf};
+pub use google_calendar::{
+    AuthFlow, CalendarConf, ColorsConf, ConflictPolicy, DeletePolicy, FieldsConf,
+    GoogleCalendarConf, GoogleCalendarGlobalConf, SendUpdates, SyncDirection, TagsMapping,
+    TokenCommand,
+};
 
 #[cfg(feature = "toggl")]
 mod toggl {
@@ -231,7 +439,7 @@ mod toggl {
     impl Default for TogglGlobalConf {
         fn default() -> Self {
             TogglGlobalConf {
-                api_token: env::var("TOGGL_API_TOKEN").unwrap(),
+                api_token: env::var("TOGGL_API_TOKEN").unwrap_or_default(),
             }
         }
     }
@@ -2,21 +2,144 @@ use app_dirs::AppDirsError;
 use chrono::ParseError as ChronoError;
 use dotenv::Error as EnvError;
 use isahc::http::Error as HttpError;
+#[cfg(any(feature = "google_calendar", feature = "toggl"))]
+use isahc::http::StatusCode;
 use isahc::Error as IsahcError;
+use orgize::ValidationError;
 use serde_json::Error as JsonError;
 use std::convert::From;
+use std::fmt;
 use std::io::Error as IOError;
 
 #[derive(Debug)]
 pub enum Error {
     AppDirs(AppDirsError),
+    /// Batched request failed with the error of its whole batch
+    #[cfg(feature = "google_calendar")]
+    Batch(String),
+    /// Calendar which doesn't exist, or isn't shared with the user
+    #[cfg(feature = "google_calendar")]
+    CalendarNotFound(String),
     Chrono(ChronoError),
+    /// Conflict left unresolved
+    #[cfg(feature = "google_calendar")]
+    Conflict(String),
     Env(EnvError),
+    /// Error response of a Google API
+    #[cfg(feature = "google_calendar")]
+    GoogleApi {
+        status: StatusCode,
+        /// Reason of the error, e.g. "notFound" or "invalid_grant"
+        reason: String,
+        message: String,
+    },
     Http(IsahcError),
     IO(IOError),
     Json(JsonError),
+    #[cfg(feature = "google_calendar")]
+    OAuth(String),
+    Org(ValidationError),
+    /// Requests still failed with this status after all retries
+    #[cfg(feature = "google_calendar")]
+    RetriesExhausted(StatusCode),
+    #[cfg(feature = "google_calendar")]
+    TimeZone(String),
+    /// Error response of the Toggl API
+    #[cfg(feature = "toggl")]
+    TogglApi {
+        status: StatusCode,
+        message: String,
+    },
+    #[cfg(feature = "google_calendar")]
+    Token(String),
 }
 
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::AppDirs(err) => write!(f, "Failed to find the user directories: {}", err),
+            #[cfg(feature = "google_calendar")]
+            Error::Batch(err) => write!(f, "Batch request failed: {}", err),
+            #[cfg(feature = "google_calendar")]
+            Error::CalendarNotFound(calendar) => write!(
+                f,
+                "Calendar \"{}\" not found. \
+                 Check the calendar ids with `orgize-sync google calendars`.",
+                calendar
+            ),
+            Error::Chrono(err) => write!(f, "Failed to parse a date: {}", err),
+            #[cfg(feature = "google_calendar")]
+            Error::Conflict(err) => write!(f, "Unresolved conflict: {}", err),
+            Error::Env(err) => write!(f, "Failed to load the .env file: {}", err),
+            #[cfg(feature = "google_calendar")]
+            Error::GoogleApi {
+                status,
+                reason,
+                message,
+            } => {
+                let message = message.trim_end_matches('.');
+
+                match (status.as_u16(), reason.as_str()) {
+                    (_, "invalid_grant") => write!(
+                        f,
+                        "The Google authorization has expired or was revoked. \
+                         Run `orgize-sync auth google` again."
+                    ),
+                    (401, _) => write!(
+                        f,
+                        "Google rejected the authorization: {}. \
+                         Run `orgize-sync auth google` again.",
+                        message
+                    ),
+                    (403, "insufficientPermissions") | (403, "ACCESS_TOKEN_SCOPE_INSUFFICIENT") => {
+                        write!(
+                            f,
+                            "Insufficient permissions: {}. Run `orgize-sync auth google` \
+                             again and grant access to Google Calendar.",
+                            message
+                        )
+                    }
+                    (404, _) => write!(f, "Not found: {}", message),
+                    (_, "") => write!(f, "Google responded with {}: {}", status, message),
+                    _ => write!(
+                        f,
+                        "Google responded with {} ({}): {}",
+                        status, reason, message
+                    ),
+                }
+            }
+            Error::Http(err) => write!(f, "HTTP request failed: {}", err),
+            Error::IO(err) => write!(f, "{}", err),
+            Error::Json(err) => write!(f, "Failed to parse JSON: {}", err),
+            #[cfg(feature = "google_calendar")]
+            Error::OAuth(err) => write!(f, "Google authorization failed: {}", err),
+            Error::Org(err) => write!(f, "Invalid org document: {:?}", err),
+            #[cfg(feature = "google_calendar")]
+            Error::RetriesExhausted(status) => write!(
+                f,
+                "Google Calendar still responded with {} after all retries. Try again later.",
+                status
+            ),
+            #[cfg(feature = "google_calendar")]
+            Error::TimeZone(err) => write!(f, "Invalid time zone: {}", err),
+            #[cfg(feature = "toggl")]
+            Error::TogglApi { status, message } => match status.as_u16() {
+                401 | 403 => write!(
+                    f,
+                    "Toggl rejected the API token: {}. Check the api_token setting \
+                     or the TOGGL_API_TOKEN environment variable.",
+                    message
+                ),
+                _ => write!(f, "Toggl responded with {}: {}", status, message),
+            },
+            #[cfg(feature = "google_calendar")]
+            Error::Token(err) => write!(f, "Failed to access the Google OAuth token: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
 impl From<AppDirsError> for Error {
     fn from(err: AppDirsError) -> Self {
         Error::AppDirs(err)
@@ -59,4 +182,10 @@ impl From<JsonError> for Error {
     }
 }
 
+impl From<ValidationError> for Error {
+    fn from(err: ValidationError) -> Self {
+        Error::Org(err)
+    }
+}
+
 pub type Result<T> = std::result::Result<T, Error>;
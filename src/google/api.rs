@@ -1,8 +1,66 @@
-use chrono::{DateTime, Utc};
-use isahc::prelude::{Request, RequestExt, ResponseExt};
-use serde::Deserialize;
+use chrono::{DateTime, SecondsFormat, Utc};
+use isahc::http::{Method, StatusCode};
+use isahc::prelude::{Request, RequestExt, Response, ResponseExt};
+use isahc::Body;
+use log::{trace, warn};
+use percent_encoding::{utf8_percent_encode, PercentEncode, NON_ALPHANUMERIC};
+use serde::{de::DeserializeOwned, Deserialize};
 
-use crate::{conf::GoogleCalendarGlobalConf, error::Result, google::models::Event};
+use crate::{
+    conf::GoogleCalendarGlobalConf,
+    error::{Error, Result},
+    google::{
+        client::Client,
+        models::{CalendarListEntry, Colors, ErrorResponse, Event},
+    },
+};
+
+/// Scope of the access tokens
+pub const CALENDAR_SCOPE: &str = "https://www.googleapis.com/auth/calendar";
+
+pub fn encode(input: &str) -> PercentEncode<'_> {
+    utf8_percent_encode(input, NON_ALPHANUMERIC)
+}
+
+/// Returns the response if successful, or the error it contains
+pub fn check(mut res: Response<Body>) -> Result<Response<Body>> {
+    let status = res.status();
+
+    if status.is_success() {
+        return Ok(res);
+    }
+
+    let text = res.text()?;
+
+    let (reason, message) = match serde_json::from_str::<ErrorResponse>(&text) {
+        Ok(ErrorResponse::Api { error }) => (
+            error
+                .errors
+                .into_iter()
+                .next()
+                .map(|error| error.reason)
+                .or(error.status)
+                .unwrap_or_default(),
+            error.message,
+        ),
+        Ok(ErrorResponse::OAuth {
+            error,
+            error_description,
+        }) => (error, error_description),
+        Err(_) => (String::new(), text),
+    };
+
+    Err(Error::GoogleApi {
+        status,
+        reason,
+        message,
+    })
+}
+
+/// Parses the JSON body of a successful response
+pub fn parse<T: DeserializeOwned>(res: Response<Body>) -> Result<T> {
+    Ok(check(res)?.json()?)
+}
 
 #[derive(Deserialize)]
 pub struct ConfirmCodeResponse {
@@ -13,20 +71,79 @@ pub struct ConfirmCodeResponse {
 
 pub async fn confirm_code(
     code: &str,
+    redirect_uri: &str,
+    code_verifier: &str,
     conf: &GoogleCalendarGlobalConf,
 ) -> Result<ConfirmCodeResponse> {
     let body = format!(
         "code={}&client_id={}&client_secret={}&\
-         redirect_uri={}&grant_type=authorization_code",
-        code, conf.client_id, conf.client_secret, conf.redirect_uri
+         redirect_uri={}&code_verifier={}&grant_type=authorization_code",
+        encode(code),
+        encode(&conf.client_id),
+        encode(&conf.client_secret),
+        encode(redirect_uri),
+        encode(code_verifier),
     );
 
     let res = Request::post("https://www.googleapis.com/oauth2/v4/token")
+        .header("content-type", "application/x-www-form-urlencoded")
+        .body(body)?
+        .send_async()
+        .await?;
+
+    parse(res)
+}
+
+#[derive(Deserialize)]
+pub struct DeviceCodeResponse {
+    pub device_code: String,
+    pub user_code: String,
+    pub verification_url: String,
+    pub interval: u64,
+}
+
+pub async fn request_device_code(conf: &GoogleCalendarGlobalConf) -> Result<DeviceCodeResponse> {
+    let body = format!(
+        "client_id={}&scope={}",
+        encode(&conf.client_id),
+        encode(CALENDAR_SCOPE),
+    );
+
+    let res = Request::post("https://oauth2.googleapis.com/device/code")
+        .header("content-type", "application/x-www-form-urlencoded")
+        .body(body)?
+        .send_async()
+        .await?;
+
+    parse(res)
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum DeviceTokenResponse {
+    Token(ConfirmCodeResponse),
+    Error { error: String },
+}
+
+pub async fn poll_device_token(
+    device_code: &str,
+    conf: &GoogleCalendarGlobalConf,
+) -> Result<DeviceTokenResponse> {
+    let body = format!(
+        "client_id={}&client_secret={}&device_code={}\
+         &grant_type=urn%3Aietf%3Aparams%3Aoauth%3Agrant-type%3Adevice_code",
+        encode(&conf.client_id),
+        encode(&conf.client_secret),
+        encode(device_code),
+    );
+
+    // pending authorizations are reported as errors
+    let res = Request::post("https://oauth2.googleapis.com/token")
         .header("content-type", "application/x-www-form-urlencoded")
         .body(body)?
         .send_async()
         .await?
-        .json::<ConfirmCodeResponse>()?;
+        .json::<DeviceTokenResponse>()?;
 
     Ok(res)
 }
@@ -43,51 +160,169 @@ pub async fn refresh_token(
 ) -> Result<RefreshTokenResponse> {
     let body = format!(
         "client_id={}&client_secret={}&refresh_token={}&grant_type=refresh_token",
-        conf.client_id, conf.client_secret, refresh_token,
+        encode(&conf.client_id),
+        encode(&conf.client_secret),
+        encode(refresh_token),
     );
 
     let res = Request::post("https://www.googleapis.com/oauth2/v4/token")
         .header("content-type", "application/x-www-form-urlencoded")
         .body(body)?
         .send_async()
-        .await?
-        .json::<RefreshTokenResponse>()?;
+        .await?;
 
-    Ok(res)
+    parse(res)
 }
 
-#[derive(Deserialize)]
-pub struct ListEventResponse {
-    updated: DateTime<Utc>,
+/// Exchanges a signed JWT assertion of a service account for an access token
+pub async fn jwt_bearer_token(token_uri: &str, assertion: &str) -> Result<RefreshTokenResponse> {
+    let body = format!(
+        "grant_type={}&assertion={}",
+        encode("urn:ietf:params:oauth:grant-type:jwt-bearer"),
+        encode(assertion),
+    );
+
+    let res = Request::post(token_uri)
+        .header("content-type", "application/x-www-form-urlencoded")
+        .body(body)?
+        .send_async()
+        .await?;
+
+    parse(res)
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+struct ListEventResponse {
     items: Vec<Event>,
+    next_page_token: Option<String>,
+    next_sync_token: Option<String>,
+}
+
+pub struct EventList {
+    pub items: Vec<Event>,
+    /// Token for retrieving only the events changed after this listing.
+    pub next_sync_token: Option<String>,
 }
 
+/// Lists all events between `time_min` and `time_max`, or only the events
+/// changed since `sync_token` was issued.
+///
+/// Falls back to a full listing if the sync token has expired.
 pub async fn list_events(
+    client: &mut Client,
     calendar_id: &str,
     time_min: DateTime<Utc>,
     time_max: DateTime<Utc>,
-    updated_min: Option<DateTime<Utc>>,
-) -> Result<ListEventResponse> {
-    let url = if let Some(updated_min) = updated_min {
-        format!(
-            "https://www.googleapis.com/calendar/v3/calendars/{}/events\
-             ?orderBy=startTime&timeMin={}&timeMax={}&updatedMin={}",
-            calendar_id,
-            time_min.to_rfc3339(),
-            time_max.to_rfc3339(),
-            updated_min.to_rfc3339(),
-        )
-    } else {
-        format!(
+    mut sync_token: Option<&str>,
+) -> Result<EventList> {
+    let mut items = Vec::new();
+    let mut page_token: Option<String> = None;
+
+    loop {
+        let mut url = format!(
             "https://www.googleapis.com/calendar/v3/calendars/{}/events\
-             ?orderBy=startTime&timeMin={}&timeMax={}",
-            calendar_id,
-            time_min.to_rfc3339(),
-            time_max.to_rfc3339(),
+             ?singleEvents=true&showDeleted=true",
+            encode(calendar_id),
+        );
+
+        if let Some(sync_token) = sync_token {
+            url += &format!("&syncToken={}", encode(sync_token));
+        } else {
+            url += &format!(
+                "&timeMin={}&timeMax={}",
+                encode(&time_min.to_rfc3339_opts(SecondsFormat::Secs, true)),
+                encode(&time_max.to_rfc3339_opts(SecondsFormat::Secs, true)),
+            );
+        }
+
+        if let Some(page_token) = &page_token {
+            url += &format!("&pageToken={}", encode(page_token));
+        }
+
+        let res = client.send(Method::GET, &url, None).await?;
+
+        if res.status() == StatusCode::GONE && sync_token.is_some() {
+            warn!("Google Calendar sync token expired. Performing a full synchronization.");
+
+            sync_token = None;
+            page_token = None;
+            items.clear();
+            continue;
+        }
+
+        if res.status() == StatusCode::NOT_FOUND {
+            return Err(Error::CalendarNotFound(calendar_id.into()));
+        }
+
+        let res: ListEventResponse = parse(res)?;
+
+        items.extend(res.items);
+
+        if res.next_page_token.is_none() {
+            return Ok(EventList {
+                items,
+                next_sync_token: res.next_sync_token,
+            });
+        }
+
+        trace!("Fetching next page of events.");
+
+        page_token = res.next_page_token;
+    }
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+struct CalendarListResponse {
+    items: Vec<CalendarListEntry>,
+    next_page_token: Option<String>,
+}
+
+/// Lists the calendars of the user
+pub async fn list_calendars(client: &mut Client) -> Result<Vec<CalendarListEntry>> {
+    let mut items = Vec::new();
+    let mut page_token: Option<String> = None;
+
+    loop {
+        let mut url = "https://www.googleapis.com/calendar/v3/users/me/calendarList".to_string();
+
+        if let Some(page_token) = &page_token {
+            url += &format!("?pageToken={}", encode(page_token));
+        }
+
+        let res: CalendarListResponse = parse(client.send(Method::GET, &url, None).await?)?;
+
+        items.extend(res.items);
+
+        if res.next_page_token.is_none() {
+            return Ok(items);
+        }
+
+        page_token = res.next_page_token;
+    }
+}
+
+pub async fn get_colors(client: &mut Client) -> Result<Colors> {
+    let res = client
+        .send(
+            Method::GET,
+            "https://www.googleapis.com/calendar/v3/colors",
+            None,
         )
-    };
+        .await?;
 
-    let res = isahc::get_async(url).await?.json::<ListEventResponse>()?;
+    parse(res)
+}
 
-    Ok(res)
+pub async fn get_event(client: &mut Client, calendar_id: &str, event_id: &str) -> Result<Event> {
+    let url = format!(
+        "https://www.googleapis.com/calendar/v3/calendars/{}/events/{}",
+        encode(calendar_id),
+        encode(event_id)
+    );
+
+    let res = client.send(Method::GET, &url, None).await?;
+
+    parse(res)
 }
@@ -1,82 +1,316 @@
+use async_std::net::TcpListener;
+use async_std::prelude::*;
+use async_std::task;
 use log::{debug, info, trace};
-use std::fs;
+use std::collections::HashMap;
 use std::io::{stdin, BufRead};
-use std::process;
+use std::path::Path;
+use std::process::{self, Command};
+use std::time;
 
-use chrono::{DateTime, Duration, Utc};
-use serde::{Deserialize, Serialize};
+use chrono::{Duration, Utc};
+use percent_encoding::percent_decode_str;
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use ring::digest::{digest, SHA256};
 
 use crate::{
-    conf::GoogleCalendarGlobalConf,
-    error::Result,
-    google::api::{confirm_code, refresh_token},
+    conf::{AuthFlow, GoogleCalendarGlobalConf},
+    error::{Error, Result},
+    google::{
+        api::{
+            confirm_code, encode, jwt_bearer_token, poll_device_token, refresh_token,
+            request_device_code, ConfirmCodeResponse, DeviceTokenResponse, CALENDAR_SCOPE,
+        },
+        service_account::ServiceAccountKey,
+        token::{self, OAuthToken},
+    },
 };
 
-#[derive(Serialize, Deserialize)]
-struct OAuthToken {
-    access_token: String,
-    expires_at: DateTime<Utc>,
-    refresh_token: String,
-}
-
 pub async fn access_token(conf: &GoogleCalendarGlobalConf) -> Result<String> {
-    let token_path = conf.token_dir.clone().join(&conf.token_filename);
-
-    debug!("Google OAuth token path: {}", token_path.display());
-
-    if let Ok(json) = fs::read_to_string(&token_path) {
-        let mut auth: OAuthToken = serde_json::from_str(&json)?;
-
+    if let Some(auth) = token::load(conf)? {
         if auth.expires_at > Utc::now() {
             Ok(auth.access_token)
+        } else if let Some(key) = &conf.service_account_key {
+            debug!("Google OAuth token expired. Requesting a new one.");
+
+            authorize_service_account(key, conf).await
         } else {
             info!("Google OAuth token expired. Refreshing.");
 
-            let res = refresh_token(&auth.refresh_token, conf).await?;
-            auth.access_token = res.access_token;
-            auth.expires_at = Utc::now() + Duration::seconds(res.expires_in);
+            refresh(auth, conf).await
+        }
+    } else if let Some(key) = &conf.service_account_key {
+        authorize_service_account(key, conf).await
+    } else {
+        // authorizing is interactive, which would block unattended runs
+        Err(Error::OAuth(
+            "no token found, run `orgize-sync auth google` first".into(),
+        ))
+    }
+}
+
+/// Requests an access token for a service account and saves it.
+///
+/// Service accounts have no refresh token: a new access token is
+/// requested with a signed JWT whenever the old one expires.
+async fn authorize_service_account(
+    key_path: &Path,
+    conf: &GoogleCalendarGlobalConf,
+) -> Result<String> {
+    let key = ServiceAccountKey::load(key_path)?;
 
-            trace!("Saving Google OAuth token.");
+    debug!("Authorizing as service account {}.", key.client_email);
 
-            fs::write(token_path, serde_json::to_string(&auth)?)?;
+    let res = jwt_bearer_token(&key.token_uri, &key.assertion(CALENDAR_SCOPE)?)
+        .await
+        .map_err(|err| match err {
+            // authorizing again doesn't help service accounts
+            Error::GoogleApi {
+                reason, message, ..
+            } if reason == "invalid_grant" => Error::OAuth(format!(
+                "the service account key was rejected: {}. \
+                 Check service_account_key and the system clock",
+                message.trim_end_matches('.')
+            )),
+            err => err,
+        })?;
 
-            Ok(auth.access_token)
-        }
+    let auth = OAuthToken {
+        access_token: res.access_token,
+        expires_at: Utc::now() + Duration::seconds(res.expires_in),
+        refresh_token: String::new(),
+    };
+
+    token::save(conf, &auth)?;
+
+    Ok(auth.access_token)
+}
+
+/// Runs the OAuth authorization flow and saves the new token.
+///
+/// By default, the authorization code is received by a listener on a
+/// loopback address. If `no_browser` is `true`, the user pastes the code
+/// from the `redirect_uri` instead. The device flow is used instead if
+/// selected by `auth_flow`, and a service account if `service_account_key`
+/// is set.
+pub async fn authorize(conf: &GoogleCalendarGlobalConf, no_browser: bool) -> Result<String> {
+    if let Some(key) = &conf.service_account_key {
+        let access_token = authorize_service_account(key, conf).await?;
+
+        info!("Google Calendar authorized.");
+
+        return Ok(access_token);
+    }
+
+    let res = match conf.auth_flow {
+        AuthFlow::Browser => authorize_browser(conf, no_browser).await?,
+        AuthFlow::Device => authorize_device(conf).await?,
+    };
+
+    let auth = OAuthToken {
+        access_token: res.access_token,
+        expires_at: Utc::now() + Duration::seconds(res.expires_in),
+        refresh_token: res.refresh_token,
+    };
+
+    token::save(conf, &auth)?;
+
+    info!("Google Calendar authorized.");
+
+    Ok(auth.access_token)
+}
+
+async fn authorize_browser(
+    conf: &GoogleCalendarGlobalConf,
+    no_browser: bool,
+) -> Result<ConfirmCodeResponse> {
+    let code_verifier = random_string(64);
+    let code_challenge = base64::encode_config(
+        &digest(&SHA256, code_verifier.as_bytes()),
+        base64::URL_SAFE_NO_PAD,
+    );
+    let state = random_string(32);
+
+    let (code, redirect_uri) = if no_browser {
+        let url = authorization_url(conf, &conf.redirect_uri, &code_challenge, &state);
+
+        info!("Please visit: {}", url);
+        info!("Follow the instructions and paste the code here (press q to quit):");
+
+        (read_code()?, conf.redirect_uri.clone())
     } else {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await?;
+        let redirect_uri = format!("http://127.0.0.1:{}", listener.local_addr()?.port());
+        let url = authorization_url(conf, &redirect_uri, &code_challenge, &state);
+
         info!(
-            "Please visit: https://accounts.google.com/o/oauth2/v2/auth\
-             ?client_id={}&redirect_uri={}&scope=https://www.googleapis.com/auth/calendar\
-             &response_type=code&access_type=offline",
-            conf.client_id, conf.redirect_uri,
+            "Opening your browser. If it doesn't open, please visit: {}",
+            url
         );
-        info!("Follow the instructions and paste the code here (press q to quit):");
 
-        for line in stdin().lock().lines() {
-            let line = line?;
-            let code = line.trim();
+        open_browser(&url);
+
+        (receive_code(&listener, &state).await?, redirect_uri)
+    };
 
-            if code.is_empty() {
-                continue;
-            } else if code == "q" {
-                process::exit(1);
-            }
+    info!("Confirming code.");
 
-            info!("Confirming code.");
+    confirm_code(&code, &redirect_uri, &code_verifier, conf).await
+}
 
-            let res = confirm_code(code, conf).await?;
+/// Runs the OAuth device authorization flow, for machines without a browser
+async fn authorize_device(conf: &GoogleCalendarGlobalConf) -> Result<ConfirmCodeResponse> {
+    let res = request_device_code(conf).await?;
 
-            let auth = OAuthToken {
-                access_token: res.access_token,
-                expires_at: Utc::now() + Duration::seconds(res.expires_in),
-                refresh_token: res.refresh_token,
-            };
+    info!(
+        "Please visit {} on any device and enter the code: {}",
+        res.verification_url, res.user_code
+    );
 
-            trace!("Saving Google OAuth token.");
+    let mut interval = res.interval;
 
-            fs::write(token_path, serde_json::to_string(&auth)?)?;
+    loop {
+        task::sleep(time::Duration::from_secs(interval)).await;
 
-            return Ok(auth.access_token);
+        match poll_device_token(&res.device_code, conf).await? {
+            DeviceTokenResponse::Token(token) => return Ok(token),
+            DeviceTokenResponse::Error { error } => match error.as_str() {
+                "authorization_pending" => trace!("Authorization pending."),
+                "slow_down" => interval += 5,
+                _ => return Err(Error::OAuth(error)),
+            },
         }
-        process::exit(1);
     }
 }
+
+fn authorization_url(
+    conf: &GoogleCalendarGlobalConf,
+    redirect_uri: &str,
+    code_challenge: &str,
+    state: &str,
+) -> String {
+    format!(
+        "https://accounts.google.com/o/oauth2/v2/auth\
+         ?client_id={}&redirect_uri={}&scope={}&response_type=code&access_type=offline\
+         &prompt=consent&code_challenge={}&code_challenge_method=S256&state={}",
+        encode(&conf.client_id),
+        encode(redirect_uri),
+        encode(CALENDAR_SCOPE),
+        code_challenge,
+        state,
+    )
+}
+
+fn random_string(len: usize) -> String {
+    thread_rng().sample_iter(&Alphanumeric).take(len).collect()
+}
+
+fn open_browser(url: &str) {
+    let res = if cfg!(target_os = "windows") {
+        Command::new("cmd").args(["/C", "start", "", url]).status()
+    } else if cfg!(target_os = "macos") {
+        Command::new("open").arg(url).status()
+    } else {
+        Command::new("xdg-open").arg(url).status()
+    };
+
+    if let Err(err) = res {
+        debug!("Failed to open browser: {}", err);
+    }
+}
+
+/// Reads the authorization code from stdin
+fn read_code() -> Result<String> {
+    for line in stdin().lock().lines() {
+        let line = line?;
+        let code = line.trim();
+
+        if code.is_empty() {
+            continue;
+        } else if code == "q" {
+            process::exit(1);
+        }
+
+        return Ok(code.to_string());
+    }
+
+    process::exit(1);
+}
+
+/// Waits for the authorization response redirected to the loopback listener
+async fn receive_code(listener: &TcpListener, state: &str) -> Result<String> {
+    loop {
+        let (mut stream, _) = listener.accept().await?;
+
+        let mut buf = vec![0; 4096];
+        let len = stream.read(&mut buf).await?;
+        let req = String::from_utf8_lossy(&buf[..len]);
+
+        // GET /?state=...&code=... HTTP/1.1
+        let params: HashMap<_, _> = req
+            .split_whitespace()
+            .nth(1)
+            .and_then(|target| target.split_once('?'))
+            .map(|(_, query)| query)
+            .unwrap_or_default()
+            .split('&')
+            .filter_map(|param| {
+                let (key, value) = param.split_once('=')?;
+                let value = percent_decode_str(value).decode_utf8_lossy();
+                Some((key.to_string(), value.to_string()))
+            })
+            .collect();
+
+        if !params.contains_key("code") && !params.contains_key("error") {
+            trace!("Ignoring request without authorization response.");
+
+            stream
+                .write_all(b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n")
+                .await?;
+            continue;
+        }
+
+        let body = "Authorization finished. You can close this window now.";
+        stream
+            .write_all(
+                format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: {}\r\n\r\n{}",
+                    body.len(),
+                    body
+                )
+                .as_bytes(),
+            )
+            .await?;
+
+        if params.get("state").map(String::as_str) != Some(state) {
+            return Err(Error::OAuth("state parameter mismatched".into()));
+        }
+
+        return match (params.get("code"), params.get("error")) {
+            (Some(code), _) => Ok(code.clone()),
+            (None, Some(error)) => Err(Error::OAuth(error.clone())),
+            (None, None) => unreachable!(),
+        };
+    }
+}
+
+/// Refreshes the access token even if it has not expired yet.
+pub async fn refresh_access_token(conf: &GoogleCalendarGlobalConf) -> Result<String> {
+    if let Some(key) = &conf.service_account_key {
+        authorize_service_account(key, conf).await
+    } else if let Some(auth) = token::load(conf)? {
+        refresh(auth, conf).await
+    } else {
+        access_token(conf).await
+    }
+}
+
+async fn refresh(mut auth: OAuthToken, conf: &GoogleCalendarGlobalConf) -> Result<String> {
+    let res = refresh_token(&auth.refresh_token, conf).await?;
+    auth.access_token = res.access_token;
+    auth.expires_at = Utc::now() + Duration::seconds(res.expires_in);
+
+    token::save(conf, &auth)?;
+
+    Ok(auth.access_token)
+}
@@ -0,0 +1,443 @@
+//! Batched writes to Google Calendar
+//!
+//! Requests are grouped into multipart requests to the batch endpoint. Each
+//! request succeeds or fails on its own, so that a rejected event doesn't
+//! abort the synchronization of a whole file.
+
+use async_std::task;
+use isahc::http::{Method, Response, StatusCode};
+use isahc::prelude::ResponseExt;
+use isahc::Body;
+use log::{debug, warn};
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use serde_json::Value;
+use std::iter;
+
+use crate::{
+    conf::SendUpdates,
+    error::{Error, Result},
+    google::{
+        api::{self, encode},
+        client::{self, Client},
+        models::Event,
+    },
+};
+
+const BATCH_URL: &str = "https://www.googleapis.com/batch/calendar/v3";
+
+/// Google Calendar accepts at most 50 requests per batch
+const MAX_BATCH_SIZE: usize = 50;
+
+/// Request, with the event id and its ETag, if known
+enum Request {
+    Insert(Box<Event>),
+    Patch(String, Option<String>, Value),
+    Delete(String, Option<String>),
+}
+
+/// Writes to a calendar, each with a key telling where it comes from
+pub struct Batch<K> {
+    calendar_id: String,
+    send_updates: SendUpdates,
+    requests: Vec<(K, Request)>,
+}
+
+impl<K> Batch<K> {
+    pub fn new(calendar_id: &str, send_updates: SendUpdates) -> Self {
+        Batch {
+            calendar_id: calendar_id.into(),
+            send_updates,
+            requests: Vec::new(),
+        }
+    }
+
+    /// Inserts an event, with a new id unless it has one
+    ///
+    /// Inserts are sent again when they fail on the server, and the id makes
+    /// sure that the event is inserted only once.
+    pub fn insert(&mut self, key: K, mut event: Event) {
+        if event.id.is_none() {
+            event.id = Some(event_id());
+        }
+
+        self.requests.push((key, Request::Insert(Box::new(event))));
+    }
+
+    /// Patches an event with the fields of `body`, unless it was changed
+    /// since its ETag was fetched
+    pub fn patch(&mut self, key: K, event_id: &str, etag: Option<&str>, body: Value) {
+        self.requests.push((
+            key,
+            Request::Patch(event_id.into(), etag.map(Into::into), body),
+        ));
+    }
+
+    /// Deletes an event, unless it was changed since its ETag was fetched
+    pub fn delete(&mut self, key: K, event_id: &str, etag: Option<&str>) {
+        self.requests
+            .push((key, Request::Delete(event_id.into(), etag.map(Into::into))));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.requests.is_empty()
+    }
+
+    /// Sends all requests, and returns their results with their keys
+    ///
+    /// Inserted and patched events are returned. Requests which were rate
+    /// limited or failed on the server are sent again in another batch, up
+    /// to `max_retries` times. When a whole batch fails, each of its
+    /// requests fails with the error of the batch.
+    pub async fn execute(self, client: &mut Client) -> Vec<(K, Result<Option<Event>>)> {
+        let mut responses: Vec<Option<Response<Body>>> = iter::repeat_with(|| None)
+            .take(self.requests.len())
+            .collect();
+        let mut errors: Vec<Option<String>> = vec![None; self.requests.len()];
+        let mut pending: Vec<usize> = (0..self.requests.len()).collect();
+        let mut retries = 0;
+
+        while !pending.is_empty() {
+            let mut failed = Vec::new();
+
+            for chunk in pending.chunks(MAX_BATCH_SIZE) {
+                debug!("Sending a batch of {} request(s).", chunk.len());
+
+                let chunk_responses = match self.send(client, chunk).await {
+                    Ok(responses) => responses,
+                    Err(err) => {
+                        warn!("A batch of {} request(s) failed: {}", chunk.len(), err);
+
+                        for &i in chunk {
+                            errors[i] = Some(err.to_string());
+                        }
+                        continue;
+                    }
+                };
+
+                for (&i, mut res) in chunk.iter().zip(chunk_responses) {
+                    if client::should_retry(&mut res).unwrap_or(false) {
+                        failed.push(i);
+                    }
+                    responses[i] = Some(res);
+                }
+            }
+
+            pending = failed;
+
+            if pending.is_empty() || retries >= client.max_retries() {
+                break;
+            }
+
+            let delay = client::backoff(retries);
+
+            warn!(
+                "{} batched request(s) failed. Retrying in {:.1}s.",
+                pending.len(),
+                delay.as_secs_f32()
+            );
+
+            task::sleep(delay).await;
+
+            retries += 1;
+        }
+
+        let mut results = Vec::with_capacity(self.requests.len());
+
+        for (i, ((key, request), res)) in self.requests.into_iter().zip(responses).enumerate() {
+            let result = match (errors[i].take(), res) {
+                (Some(err), _) => Err(Error::Batch(err)),
+                (None, Some(res)) if pending.contains(&i) => {
+                    Err(Error::RetriesExhausted(res.status()))
+                }
+                (None, Some(res)) => match request {
+                    // already deleted, or even purged
+                    Request::Delete(..)
+                        if res.status() == StatusCode::GONE
+                            || res.status() == StatusCode::NOT_FOUND =>
+                    {
+                        Ok(None)
+                    }
+                    Request::Delete(..) => api::check(res).map(|_| None),
+                    // inserted by an attempt whose response was lost
+                    Request::Insert(event) if res.status() == StatusCode::CONFLICT => {
+                        let id = event.id.as_deref().unwrap_or_default();
+                        api::get_event(client, &self.calendar_id, id)
+                            .await
+                            .map(Some)
+                    }
+                    _ => api::parse(res).map(Some),
+                },
+                // every request got a response or an error
+                (None, None) => unreachable!(),
+            };
+
+            results.push((key, result));
+        }
+
+        results
+    }
+
+    /// Sends the given requests in one batch, and returns their responses
+    /// in the same order
+    async fn send(&self, client: &mut Client, indices: &[usize]) -> Result<Vec<Response<Body>>> {
+        let boundary: String = thread_rng().sample_iter(&Alphanumeric).take(24).collect();
+
+        let mut body = String::new();
+
+        for (n, &i) in indices.iter().enumerate() {
+            let request = &self.requests[i].1;
+            let (method, path) = self.request_line(request);
+
+            body += &format!(
+                "--{}\r\nContent-Type: application/http\r\nContent-ID: <item{}>\r\n\r\n",
+                boundary, n
+            );
+            body += &format!("{} {} HTTP/1.1\r\n", method, path);
+
+            if let Request::Patch(_, Some(etag), _) | Request::Delete(_, Some(etag)) = request {
+                body += &format!("If-Match: {}\r\n", etag);
+            }
+
+            let json = match request {
+                Request::Insert(event) => Some(serde_json::to_string(event)?),
+                Request::Patch(_, _, event) => Some(event.to_string()),
+                Request::Delete(..) => None,
+            };
+
+            match json {
+                Some(json) => {
+                    body += "Content-Type: application/json\r\n\r\n";
+                    body += &json;
+                    body += "\r\n";
+                }
+                None => body += "\r\n",
+            }
+        }
+
+        body += &format!("--{}--\r\n", boundary);
+
+        let res = client
+            .send_with_content_type(
+                Method::POST,
+                BATCH_URL,
+                Some(body.into_bytes()),
+                &format!("multipart/mixed; boundary={}", boundary),
+            )
+            .await?;
+
+        let mut res = api::check(res)?;
+
+        let boundary = res
+            .headers()
+            .get("content-type")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.split("boundary=").nth(1))
+            .map(|boundary| boundary.trim_matches('"').to_string())
+            .unwrap_or_default();
+
+        let mut responses: Vec<Option<Response<Body>>> =
+            iter::repeat_with(|| None).take(indices.len()).collect();
+
+        for (n, res) in parse_parts(&res.text()?, &boundary)? {
+            if let Some(response) = responses.get_mut(n) {
+                *response = Some(res);
+            }
+        }
+
+        responses
+            .into_iter()
+            .map(|res| match res {
+                Some(res) => Ok(res),
+                // sent again like a failure on the server
+                None => Ok(Response::builder()
+                    .status(StatusCode::INTERNAL_SERVER_ERROR)
+                    .body(Body::empty())?),
+            })
+            .collect()
+    }
+
+    fn request_line(&self, request: &Request) -> (Method, String) {
+        let events = format!(
+            "/calendar/v3/calendars/{}/events",
+            encode(&self.calendar_id)
+        );
+        let query = format!("?sendUpdates={}", self.send_updates.as_str());
+
+        match request {
+            Request::Insert(_) => (Method::POST, events + &query),
+            Request::Patch(id, ..) => {
+                (Method::PATCH, format!("{}/{}{}", events, encode(id), query))
+            }
+            Request::Delete(id, _) => (
+                Method::DELETE,
+                format!("{}/{}{}", events, encode(id), query),
+            ),
+        }
+    }
+}
+
+/// Returns a random event id, in the base32hex encoding required by Google
+/// Calendar
+fn event_id() -> String {
+    const CHARS: &[u8] = b"0123456789abcdefghijklmnopqrstuv";
+
+    let mut rng = thread_rng();
+
+    (0..26)
+        .map(|_| CHARS[rng.gen_range(0, CHARS.len())] as char)
+        .collect()
+}
+
+/// Parses the parts of a batch response into responses, with the position
+/// of their request in the batch
+fn parse_parts(text: &str, boundary: &str) -> Result<Vec<(usize, Response<Body>)>> {
+    let text = text.replace("\r\n", "\n");
+    let mut responses = Vec::new();
+
+    for part in text.split(&format!("--{}", boundary)).skip(1) {
+        if part.starts_with("--") {
+            break;
+        }
+
+        let (headers, message) = match part.trim_start().split_once("\n\n") {
+            Some(part) => part,
+            None => continue,
+        };
+
+        // e.g. `Content-ID: <response-item3>`
+        let n = headers.lines().find_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            if !name.eq_ignore_ascii_case("content-id") {
+                return None;
+            }
+            value
+                .trim()
+                .trim_start_matches('<')
+                .trim_end_matches('>')
+                .strip_prefix("response-item")?
+                .parse::<usize>()
+                .ok()
+        });
+
+        // e.g. `HTTP/1.1 200 OK`
+        let status = message
+            .lines()
+            .next()
+            .and_then(|line| line.split_whitespace().nth(1))
+            .and_then(|status| status.parse::<u16>().ok());
+
+        let body = message
+            .split_once("\n\n")
+            .map(|(_, body)| body.trim())
+            .unwrap_or_default();
+
+        if let (Some(n), Some(status)) = (n, status) {
+            responses.push((
+                n,
+                Response::builder()
+                    .status(status)
+                    .body(Body::from(body.to_string()))?,
+            ));
+        }
+    }
+
+    Ok(responses)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Response to a batch of four requests, as sent by Google Calendar
+    const RESPONSE: &str = "--batch_Xy7qJm3Zt\r
+Content-Type: application/http\r
+Content-ID: <response-item2>\r
+\r
+HTTP/1.1 412 Precondition Failed\r
+Content-Type: application/json; charset=UTF-8\r
+\r
+{\"error\":{\"errors\":[{\"domain\":\"global\",\"reason\":\"conditionNotMet\",\"message\":\"Precondition Failed\"}],\"code\":412,\"message\":\"Precondition Failed\"}}\r
+--batch_Xy7qJm3Zt\r
+Content-Type: application/http\r
+Content-ID: <response-item0>\r
+\r
+HTTP/1.1 200 OK\r
+Content-Type: application/json; charset=UTF-8\r
+ETag: \"3181161784712000\"\r
+\r
+{\"kind\":\"calendar#event\",\"etag\":\"\\\"3181161784712000\\\"\",\"id\":\"a1b2c3d4e5\",\"status\":\"confirmed\",\"summary\":\"Review\"}\r
+--batch_Xy7qJm3Zt\r
+Content-Type: application/http\r
+Content-ID: <response-item3>\r
+\r
+HTTP/1.1 404 Not Found\r
+Content-Type: application/json; charset=UTF-8\r
+\r
+{\"error\":{\"errors\":[{\"domain\":\"global\",\"reason\":\"notFound\",\"message\":\"Not Found\"}],\"code\":404,\"message\":\"Not Found\"}}\r
+--batch_Xy7qJm3Zt--\r
+";
+
+    fn parts() -> Vec<(usize, Response<Body>)> {
+        parse_parts(RESPONSE, "batch_Xy7qJm3Zt").unwrap()
+    }
+
+    #[test]
+    fn parse_parts_out_of_order() {
+        let parts: Vec<_> = parts()
+            .into_iter()
+            .map(|(n, res)| (n, res.status()))
+            .collect();
+
+        assert_eq!(
+            parts,
+            vec![
+                (2, StatusCode::PRECONDITION_FAILED),
+                (0, StatusCode::OK),
+                (3, StatusCode::NOT_FOUND),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_parts_missing_part() {
+        assert!(parts().iter().all(|(n, _)| *n != 1));
+    }
+
+    #[test]
+    fn parse_parts_bodies() {
+        for (n, res) in parts() {
+            match n {
+                0 => {
+                    let event: Event = api::parse(res).unwrap();
+                    assert_eq!(event.id.as_deref(), Some("a1b2c3d4e5"));
+                    assert_eq!(event.summary, "Review");
+                }
+                2 => match api::check(res) {
+                    Err(Error::GoogleApi { status, reason, .. }) => {
+                        assert_eq!(status, StatusCode::PRECONDITION_FAILED);
+                        assert_eq!(reason, "conditionNotMet");
+                    }
+                    _ => panic!("expected a precondition failure"),
+                },
+                3 => match api::check(res) {
+                    Err(Error::GoogleApi { status, reason, .. }) => {
+                        assert_eq!(status, StatusCode::NOT_FOUND);
+                        assert_eq!(reason, "notFound");
+                    }
+                    _ => panic!("expected a missing event"),
+                },
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    #[test]
+    fn event_ids() {
+        let id = event_id();
+
+        assert_eq!(id.len(), 26);
+        assert!(id
+            .chars()
+            .all(|c| c.is_ascii_digit() || ('a'..='v').contains(&c)));
+    }
+}
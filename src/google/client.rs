@@ -0,0 +1,266 @@
+use async_std::task;
+use isahc::http::{Method, Request, Response, StatusCode};
+use isahc::prelude::RequestExt;
+use isahc::Body;
+use log::{info, warn};
+use rand::{thread_rng, Rng};
+use std::io::Read;
+use std::time::Duration;
+
+use crate::{
+    conf::GoogleCalendarGlobalConf,
+    error::{Error, Result},
+    google::{auth, models::ErrorResponse},
+};
+
+/// Longest delay between two retries, in seconds
+const MAX_BACKOFF: u64 = 64;
+
+/// Google API client, authenticated with an OAuth access token
+pub struct Client {
+    conf: GoogleCalendarGlobalConf,
+    access_token: Option<String>,
+}
+
+impl Client {
+    pub fn new(conf: GoogleCalendarGlobalConf) -> Self {
+        Client {
+            conf,
+            access_token: None,
+        }
+    }
+
+    pub fn max_retries(&self) -> u32 {
+        self.conf.max_retries
+    }
+
+    /// Sends a request with a JSON body, if any.
+    pub async fn send(
+        &mut self,
+        method: Method,
+        url: &str,
+        body: Option<Vec<u8>>,
+    ) -> Result<Response<Body>> {
+        self.send_with_content_type(method, url, body, "application/json")
+            .await
+    }
+
+    /// Sends a request with a body of the given content type, if any.
+    ///
+    /// Requests which were rate limited or failed on the server are retried
+    /// up to `max_retries` times, waiting as told by the `Retry-After`
+    /// header, or with an exponential backoff otherwise.
+    pub async fn send_with_content_type(
+        &mut self,
+        method: Method,
+        url: &str,
+        body: Option<Vec<u8>>,
+        content_type: &str,
+    ) -> Result<Response<Body>> {
+        let mut retries = 0;
+
+        loop {
+            let mut res = self
+                .send_authorized(&method, url, &body, content_type)
+                .await?;
+
+            if !should_retry(&mut res)? {
+                return Ok(res);
+            }
+
+            if retries >= self.conf.max_retries {
+                return Err(Error::RetriesExhausted(res.status()));
+            }
+
+            let delay = retry_after(&res).unwrap_or_else(|| backoff(retries));
+
+            warn!(
+                "Google Calendar responded with {}. Retrying in {:.1}s.",
+                res.status(),
+                delay.as_secs_f32()
+            );
+
+            task::sleep(delay).await;
+
+            retries += 1;
+        }
+    }
+
+    /// Sends a request once.
+    ///
+    /// If the access token is rejected, it is refreshed and the request is
+    /// retried once.
+    async fn send_authorized(
+        &mut self,
+        method: &Method,
+        url: &str,
+        body: &Option<Vec<u8>>,
+        content_type: &str,
+    ) -> Result<Response<Body>> {
+        let access_token = match &self.access_token {
+            Some(access_token) => access_token.clone(),
+            None => auth::access_token(&self.conf).await?,
+        };
+
+        let res = request(method, url, body, content_type, &access_token).await?;
+
+        if res.status() != StatusCode::UNAUTHORIZED {
+            self.access_token = Some(access_token);
+            return Ok(res);
+        }
+
+        info!("Google OAuth token was rejected. Refreshing.");
+
+        let access_token = auth::refresh_access_token(&self.conf).await?;
+        let res = request(method, url, body, content_type, &access_token).await?;
+        self.access_token = Some(access_token);
+
+        Ok(res)
+    }
+}
+
+async fn request(
+    method: &Method,
+    url: &str,
+    body: &Option<Vec<u8>>,
+    content_type: &str,
+    access_token: &str,
+) -> Result<Response<Body>> {
+    let mut builder = Request::builder();
+
+    builder
+        .method(method.clone())
+        .uri(url)
+        .header("authorization", format!("Bearer {}", access_token));
+
+    let req = match body {
+        Some(body) => builder
+            .header("content-type", content_type)
+            .body(Body::from(body.clone()))?,
+        None => builder.body(Body::empty())?,
+    };
+
+    Ok(req.send_async().await?)
+}
+
+/// Returns `true` if the request was rate limited or failed on the server
+///
+/// Google also uses 403 for exceeded rate limits, so the body of those
+/// responses is checked, and kept for the caller.
+pub fn should_retry(res: &mut Response<Body>) -> Result<bool> {
+    let status = res.status();
+
+    if status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error() {
+        return Ok(true);
+    }
+
+    if status != StatusCode::FORBIDDEN {
+        return Ok(false);
+    }
+
+    let mut body = Vec::new();
+    res.body_mut().read_to_end(&mut body)?;
+
+    let rate_limited = match serde_json::from_slice::<ErrorResponse>(&body) {
+        Ok(ErrorResponse::Api { error }) => error.errors.iter().any(|error| {
+            error.reason == "rateLimitExceeded" || error.reason == "userRateLimitExceeded"
+        }),
+        _ => false,
+    };
+
+    *res.body_mut() = Body::from(body);
+
+    Ok(rate_limited)
+}
+
+/// Returns the delay requested by the `Retry-After` header, in seconds,
+/// up to `MAX_BACKOFF`
+fn retry_after(res: &Response<Body>) -> Option<Duration> {
+    res.headers()
+        .get("retry-after")?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .map(|seconds| Duration::from_secs(seconds.min(MAX_BACKOFF)))
+}
+
+/// Returns the delay before the given retry: 1s, 2s, 4s, ... with up to one
+/// more second of jitter
+pub fn backoff(retries: u32) -> Duration {
+    let seconds = 2u64.saturating_pow(retries).min(MAX_BACKOFF);
+
+    Duration::from_secs(seconds) + Duration::from_millis(thread_rng().gen_range(0, 1000))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use isahc::prelude::ResponseExt;
+
+    fn response(status: u16, body: &str) -> Response<Body> {
+        Response::builder()
+            .status(status)
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    fn forbidden(reason: &str) -> Response<Body> {
+        response(
+            403,
+            &format!(
+                r#"{{"error":{{"errors":[{{"domain":"usageLimits","reason":"{}","message":"Forbidden"}}],"code":403,"message":"Forbidden"}}}}"#,
+                reason
+            ),
+        )
+    }
+
+    #[test]
+    fn retry_rate_limits_and_server_errors() {
+        assert!(should_retry(&mut response(429, "")).unwrap());
+        assert!(should_retry(&mut response(500, "")).unwrap());
+        assert!(should_retry(&mut response(503, "")).unwrap());
+        assert!(should_retry(&mut forbidden("rateLimitExceeded")).unwrap());
+        assert!(should_retry(&mut forbidden("userRateLimitExceeded")).unwrap());
+    }
+
+    #[test]
+    fn no_retry_of_other_errors() {
+        assert!(!should_retry(&mut response(200, "{}")).unwrap());
+        assert!(!should_retry(&mut response(404, "")).unwrap());
+        assert!(!should_retry(&mut response(412, "")).unwrap());
+        assert!(!should_retry(&mut forbidden("insufficientPermissions")).unwrap());
+        assert!(!should_retry(&mut response(403, "Forbidden")).unwrap());
+    }
+
+    #[test]
+    fn retry_keeps_body() {
+        let mut res = forbidden("insufficientPermissions");
+        should_retry(&mut res).unwrap();
+
+        assert!(res.text().unwrap().contains("insufficientPermissions"));
+    }
+
+    #[test]
+    fn backoff_bounds() {
+        for (retries, seconds) in [(0, 1), (1, 2), (3, 8), (6, MAX_BACKOFF), (64, MAX_BACKOFF)] {
+            let delay = backoff(retries);
+
+            assert!(delay >= Duration::from_secs(seconds));
+            assert!(delay < Duration::from_secs(seconds + 1));
+        }
+    }
+
+    #[test]
+    fn retry_after_is_capped() {
+        let mut res = response(429, "");
+        res.headers_mut()
+            .insert("retry-after", "3600".parse().unwrap());
+        assert_eq!(retry_after(&res), Some(Duration::from_secs(MAX_BACKOFF)));
+
+        res.headers_mut()
+            .insert("retry-after", "5".parse().unwrap());
+        assert_eq!(retry_after(&res), Some(Duration::from_secs(5)));
+    }
+}
@@ -0,0 +1,441 @@
+//! Conversions between org timestamps and event times
+
+use chrono::{
+    DateTime, Datelike, Duration, LocalResult, NaiveDate, NaiveDateTime, Offset, TimeZone,
+    Timelike, Utc,
+};
+use chrono_tz::Tz;
+use log::warn;
+use orgize::elements::{Datetime, Timestamp};
+use std::iter;
+
+use crate::{
+    conf::{GoogleCalendarConf, GoogleCalendarGlobalConf},
+    error::{Error, Result},
+    google::models::{Event, EventDateTime},
+};
+
+/// Returns the time zone of an org file, in order of preference: the
+/// time zone of the file, the global one and the system one.
+pub fn time_zone(conf: &GoogleCalendarConf, global: &GoogleCalendarGlobalConf) -> Result<Tz> {
+    if let Some(name) = conf.time_zone.as_ref().or(global.time_zone.as_ref()) {
+        return name.parse().map_err(Error::TimeZone);
+    }
+
+    match iana_time_zone::get_timezone().map(|name| name.parse()) {
+        Ok(Ok(tz)) => Ok(tz),
+        Ok(Err(err)) => {
+            warn!("Unknown system time zone: {}. Using UTC.", err);
+            Ok(Tz::UTC)
+        }
+        Err(err) => {
+            warn!("Failed to detect the system time zone: {}. Using UTC.", err);
+            Ok(Tz::UTC)
+        }
+    }
+}
+
+/// Start and optional end of an org timestamp, whose dates exist
+#[derive(Clone)]
+pub struct TimeRange {
+    pub start: Datetime<'static>,
+    pub end: Option<Datetime<'static>>,
+}
+
+impl TimeRange {
+    /// Returns the range of a timestamp, or `None` for diary timestamps and
+    /// timestamps with a date which doesn't exist, e.g. `<2026-02-30 Mon>`
+    pub fn from_timestamp(timestamp: &Timestamp) -> Option<Self> {
+        let range = match timestamp {
+            Timestamp::Active { start, .. } | Timestamp::Inactive { start, .. } => {
+                Some(TimeRange {
+                    start: start.clone().into_owned(),
+                    end: None,
+                })
+            }
+            Timestamp::ActiveRange { start, end, .. }
+            | Timestamp::InactiveRange { start, end, .. } => Some(TimeRange {
+                start: start.clone().into_owned(),
+                end: Some(end.clone().into_owned()),
+            }),
+            Timestamp::Diary { .. } => None,
+        }?;
+
+        // orgize doesn't check the dates it parses
+        for datetime in iter::once(&range.start).chain(&range.end) {
+            if naive_datetime(datetime).is_none() {
+                warn!(
+                    "Invalid date {}-{:02}-{:02} in a timestamp. Skipping it.",
+                    datetime.year, datetime.month, datetime.day
+                );
+                return None;
+            }
+        }
+
+        Some(range)
+    }
+
+    /// Returns `true` if this range overlaps with the given window
+    pub fn overlaps(&self, time_min: DateTime<Utc>, time_max: DateTime<Utc>, tz: Tz) -> bool {
+        let start = to_utc(&self.start, tz);
+        let end = self.end.as_ref().map_or(start, |end| to_utc(end, tz));
+
+        end >= time_min && start <= time_max
+    }
+
+    /// Converts this range into the start and end of an event
+    ///
+    /// A range without time becomes an all-day event, whose end date is
+    /// exclusive. A timestamp with time but without end lasts one hour.
+    pub fn to_event_times(&self, tz: Tz) -> (EventDateTime, EventDateTime) {
+        if self.start.hour.is_none() {
+            let start = valid_date(&self.start);
+            let end = self.end.as_ref().map_or(start, valid_date);
+
+            return (start.into(), (end.max(start) + Duration::days(1)).into());
+        }
+
+        let start = to_utc(&self.start, tz);
+        let end = match &self.end {
+            // a date-only end includes the whole day
+            Some(end) if end.hour.is_none() => to_utc(end, tz) + Duration::days(1),
+            Some(end) => to_utc(end, tz),
+            None => start + Duration::hours(1),
+        };
+
+        (event_date_time(start, tz), event_date_time(end, tz))
+    }
+}
+
+fn event_date_time(date_time: DateTime<Utc>, tz: Tz) -> EventDateTime {
+    EventDateTime {
+        date_time: Some(date_time.with_timezone(&tz).to_rfc3339()),
+        time_zone: Some(tz.name().into()),
+        ..Default::default()
+    }
+}
+
+/// Returns the date of an org datetime, if it exists
+fn naive_date(datetime: &Datetime) -> Option<NaiveDate> {
+    NaiveDate::from_ymd_opt(
+        datetime.year.into(),
+        datetime.month.into(),
+        datetime.day.into(),
+    )
+}
+
+/// Returns the date and time of an org datetime, if they exist
+fn naive_datetime(datetime: &Datetime) -> Option<NaiveDateTime> {
+    naive_date(datetime)?.and_hms_opt(
+        datetime.hour.unwrap_or_default().into(),
+        datetime.minute.unwrap_or_default().into(),
+        0,
+    )
+}
+
+fn valid_date(datetime: &Datetime) -> NaiveDate {
+    naive_date(datetime).expect("dates of time ranges exist")
+}
+
+/// Converts a floating org datetime in the given time zone to UTC
+///
+/// A time occurring twice when clocks go back resolves to the first
+/// occurrence. A time skipped when clocks go forward is shifted forward by
+/// the length of the gap.
+///
+/// The datetime must exist, as in time ranges and event times.
+pub fn to_utc(datetime: &Datetime, tz: Tz) -> DateTime<Utc> {
+    let naive = naive_datetime(datetime).expect("dates of time ranges exist");
+
+    match tz.from_local_datetime(&naive) {
+        LocalResult::Single(date_time) | LocalResult::Ambiguous(date_time, _) => {
+            date_time.with_timezone(&Utc)
+        }
+        LocalResult::None => {
+            // offset before the gap
+            let offset = tz
+                .offset_from_utc_datetime(&(naive - Duration::days(1)))
+                .fix();
+
+            Utc.from_utc_datetime(&(naive - Duration::seconds(offset.local_minus_utc().into())))
+        }
+    }
+}
+
+/// Converts an event time to a floating org datetime in the given time zone
+pub fn to_datetime(event_date_time: &EventDateTime, tz: Tz) -> Result<Option<Datetime<'static>>> {
+    let naive = if let Some(date_time) = &event_date_time.date_time {
+        DateTime::parse_from_rfc3339(date_time)?
+            .with_timezone(&tz)
+            .naive_local()
+    } else if let Some(date) = &event_date_time.date {
+        return Ok(Some(date_from_naive(NaiveDate::parse_from_str(
+            date, "%Y-%m-%d",
+        )?)));
+    } else {
+        return Ok(None);
+    };
+
+    Ok(Some(datetime_from_naive(naive, true)))
+}
+
+fn datetime_from_naive(naive: NaiveDateTime, with_time: bool) -> Datetime<'static> {
+    Datetime {
+        year: naive.year() as u16,
+        month: naive.month() as u8,
+        day: naive.day() as u8,
+        dayname: naive.format("%a").to_string().into(),
+        hour: Some(naive.hour() as u8).filter(|_| with_time),
+        minute: Some(naive.minute() as u8).filter(|_| with_time),
+    }
+}
+
+fn date_from_naive(date: NaiveDate) -> Datetime<'static> {
+    datetime_from_naive(date.and_hms_opt(0, 0, 0).unwrap(), false)
+}
+
+pub fn event_start(event: &Event, tz: Tz) -> Option<DateTime<Utc>> {
+    to_datetime(&event.start, tz)
+        .ok()?
+        .map(|start| to_utc(&start, tz))
+}
+
+/// Converts the time of an event to an org timestamp
+///
+/// All-day events spanning several days become date ranges, with the
+/// exclusive end date of the event turned into the last day of the range.
+pub fn event_timestamp(event: &Event, tz: Tz) -> Result<Option<Timestamp<'static>>> {
+    let start = to_datetime(&event.start, tz)?;
+    let end = to_datetime(&event.end, tz)?;
+
+    Ok(match (start, end) {
+        (Some(start), Some(end)) if start.hour.is_some() => Some(Timestamp::ActiveRange {
+            start,
+            end,
+            repeater: None,
+            delay: None,
+        }),
+        (Some(start), Some(end)) => {
+            let first: NaiveDate = (&start).into();
+            let last: NaiveDate = (&end).into();
+            let last = last - Duration::days(1);
+
+            if last > first {
+                Some(Timestamp::ActiveRange {
+                    start,
+                    end: date_from_naive(last),
+                    repeater: None,
+                    delay: None,
+                })
+            } else {
+                Some(Timestamp::Active {
+                    start,
+                    repeater: None,
+                    delay: None,
+                })
+            }
+        }
+        (Some(start), None) => Some(Timestamp::Active {
+            start,
+            repeater: None,
+            delay: None,
+        }),
+        _ => None,
+    })
+}
+
+pub fn same_time(a: &EventDateTime, b: &EventDateTime) -> bool {
+    match (&a.date_time, &b.date_time) {
+        (Some(a), Some(b)) => {
+            match (
+                DateTime::parse_from_rfc3339(a),
+                DateTime::parse_from_rfc3339(b),
+            ) {
+                (Ok(a), Ok(b)) => a == b,
+                _ => false,
+            }
+        }
+        (None, None) => a.date == b.date,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono_tz::Europe::Berlin;
+
+    fn datetime(year: u16, month: u8, day: u8, hour: u8, minute: u8) -> Datetime<'static> {
+        Datetime {
+            year,
+            month,
+            day,
+            dayname: "".into(),
+            hour: Some(hour),
+            minute: Some(minute),
+        }
+    }
+
+    fn date(year: u16, month: u8, day: u8) -> Datetime<'static> {
+        Datetime {
+            hour: None,
+            minute: None,
+            ..datetime(year, month, day, 0, 0)
+        }
+    }
+
+    fn event(start: EventDateTime, end: EventDateTime) -> Event {
+        Event {
+            start,
+            end,
+            ..Default::default()
+        }
+    }
+
+    /// Formats a timestamp like org, without day names
+    fn text(timestamp: &Timestamp) -> String {
+        let datetime = |datetime: &Datetime| {
+            let mut text = format!(
+                "{}-{:02}-{:02}",
+                datetime.year, datetime.month, datetime.day
+            );
+            if let (Some(hour), Some(minute)) = (datetime.hour, datetime.minute) {
+                text += &format!(" {:02}:{:02}", hour, minute);
+            }
+            text
+        };
+
+        match timestamp {
+            Timestamp::Active { start, .. } => format!("<{}>", datetime(start)),
+            Timestamp::ActiveRange { start, end, .. } => {
+                format!("<{}>--<{}>", datetime(start), datetime(end))
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn round_trip(range: TimeRange) -> (EventDateTime, EventDateTime, String) {
+        let (start, end) = range.to_event_times(Berlin);
+        let timestamp = event_timestamp(&event(start.clone(), end.clone()), Berlin)
+            .unwrap()
+            .unwrap();
+
+        (start, end, text(&timestamp))
+    }
+
+    #[test]
+    fn single_day() {
+        let (start, end, text) = round_trip(TimeRange {
+            start: date(2026, 10, 1),
+            end: None,
+        });
+
+        assert_eq!(start.date.as_deref(), Some("2026-10-01"));
+        assert_eq!(end.date.as_deref(), Some("2026-10-02"));
+        assert_eq!(text, "<2026-10-01>");
+    }
+
+    #[test]
+    fn date_range() {
+        let (start, end, text) = round_trip(TimeRange {
+            start: date(2026, 10, 1),
+            end: Some(date(2026, 10, 3)),
+        });
+
+        assert_eq!(start.date.as_deref(), Some("2026-10-01"));
+        assert_eq!(end.date.as_deref(), Some("2026-10-04"));
+        assert_eq!(text, "<2026-10-01>--<2026-10-03>");
+    }
+
+    #[test]
+    fn time_range() {
+        let (start, end, text) = round_trip(TimeRange {
+            start: datetime(2026, 10, 1, 9, 0),
+            end: Some(datetime(2026, 10, 1, 10, 30)),
+        });
+
+        assert_eq!(
+            start.date_time.as_deref(),
+            Some("2026-10-01T09:00:00+02:00")
+        );
+        assert_eq!(start.time_zone.as_deref(), Some("Europe/Berlin"));
+        assert_eq!(end.date_time.as_deref(), Some("2026-10-01T10:30:00+02:00"));
+        assert_eq!(text, "<2026-10-01 09:00>--<2026-10-01 10:30>");
+    }
+
+    #[test]
+    fn time_without_end() {
+        let (_, end, _) = round_trip(TimeRange {
+            start: datetime(2026, 10, 1, 9, 0),
+            end: None,
+        });
+
+        assert_eq!(end.date_time.as_deref(), Some("2026-10-01T10:00:00+02:00"));
+    }
+
+    #[test]
+    fn time_with_date_end() {
+        // the end date is included in the event
+        let (start, end, text) = round_trip(TimeRange {
+            start: datetime(2026, 10, 1, 9, 0),
+            end: Some(date(2026, 10, 2)),
+        });
+
+        assert_eq!(
+            start.date_time.as_deref(),
+            Some("2026-10-01T09:00:00+02:00")
+        );
+        assert_eq!(end.date_time.as_deref(), Some("2026-10-03T00:00:00+02:00"));
+        assert_eq!(text, "<2026-10-01 09:00>--<2026-10-03 00:00>");
+    }
+
+    fn utc(text: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(text)
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    #[test]
+    fn invalid_dates() {
+        let timestamp = Timestamp::Active {
+            start: datetime(2026, 2, 30, 9, 0),
+            repeater: None,
+            delay: None,
+        };
+        assert!(TimeRange::from_timestamp(&timestamp).is_none());
+
+        let timestamp = Timestamp::ActiveRange {
+            start: datetime(2026, 2, 27, 9, 0),
+            end: datetime(2026, 2, 27, 25, 0),
+            repeater: None,
+            delay: None,
+        };
+        assert!(TimeRange::from_timestamp(&timestamp).is_none());
+    }
+
+    #[test]
+    fn to_utc_single() {
+        assert_eq!(
+            to_utc(&datetime(2026, 6, 1, 12, 0), Berlin),
+            utc("2026-06-01T10:00:00Z")
+        );
+    }
+
+    #[test]
+    fn to_utc_gap() {
+        // clocks go from 02:00 to 03:00, so 02:30 is 03:30 CEST
+        assert_eq!(
+            to_utc(&datetime(2026, 3, 29, 2, 30), Berlin),
+            utc("2026-03-29T01:30:00Z")
+        );
+    }
+
+    #[test]
+    fn to_utc_overlap() {
+        // clocks go from 03:00 back to 02:00, so 02:30 is first in CEST
+        assert_eq!(
+            to_utc(&datetime(2026, 10, 25, 2, 30), Berlin),
+            utc("2026-10-25T00:30:00Z")
+        );
+    }
+}
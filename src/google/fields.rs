@@ -0,0 +1,515 @@
+//! Mappings between headline fields and event fields
+
+use log::warn;
+
+use crate::{
+    conf::{ColorsConf, FieldsConf, TagsMapping},
+    google::models::{Attendee, Event, EventReminder, Reminders},
+};
+
+/// Private extended property holding the tags of the headline
+pub const TAGS_PROPERTY: &str = "orgTags";
+
+/// Prefix of the description line holding the tags of the headline
+const TAGS_PREFIX: &str = "Tags: ";
+
+/// Fields of a headline read from an event
+pub struct Fields {
+    /// Section content, if descriptions are synchronized
+    pub body: Option<String>,
+    pub location: Option<String>,
+    /// Tags, if stored in the event
+    pub tags: Option<Vec<String>>,
+}
+
+/// Renders tags as in an org headline, e.g. `:work:meeting:`
+fn format_tags(tags: &[String]) -> String {
+    if tags.is_empty() {
+        String::new()
+    } else {
+        format!(":{}:", tags.join(":"))
+    }
+}
+
+fn parse_tags(text: &str) -> Vec<String> {
+    text.trim()
+        .split(':')
+        .filter(|tag| !tag.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+/// Writes the section content, location and tags of a headline into an event
+pub fn write_fields(
+    event: &mut Event,
+    conf: &FieldsConf,
+    body: &str,
+    location: Option<&str>,
+    tags: &[String],
+) {
+    let mut description = if conf.description {
+        body.to_string()
+    } else {
+        String::new()
+    };
+
+    match conf.tags {
+        TagsMapping::Description if !tags.is_empty() => {
+            if !description.is_empty() {
+                description.push_str("\n\n");
+            }
+            description.push_str(TAGS_PREFIX);
+            description.push_str(&format_tags(tags));
+        }
+        TagsMapping::Extended => {
+            event
+                .extended_properties
+                .get_or_insert_with(Default::default)
+                .private
+                .insert(TAGS_PROPERTY.into(), format_tags(tags));
+        }
+        _ => (),
+    }
+
+    event.description = Some(description).filter(|description| !description.is_empty());
+
+    if conf.location.is_some() {
+        event.location = location.map(Into::into);
+    }
+}
+
+/// Reads the section content, location and tags of a headline from an event
+pub fn read_fields(event: &Event, conf: &FieldsConf) -> Fields {
+    let mut description = event.description.clone().unwrap_or_default();
+
+    let tags = match conf.tags {
+        TagsMapping::Description => {
+            let (rest, last) = match description.rfind('\n') {
+                Some(i) => (&description[..i], &description[i + 1..]),
+                None => ("", description.as_str()),
+            };
+
+            match last.strip_prefix(TAGS_PREFIX) {
+                Some(tags) => {
+                    let tags = parse_tags(tags);
+                    description = rest.trim_end().to_string();
+                    Some(tags)
+                }
+                None => Some(Vec::new()),
+            }
+        }
+        TagsMapping::Extended => event
+            .extended_properties
+            .as_ref()
+            .and_then(|properties| properties.private.get(TAGS_PROPERTY))
+            .map(|tags| parse_tags(tags)),
+        TagsMapping::None => None,
+    };
+
+    Fields {
+        body: Some(description).filter(|_| conf.description),
+        location: event
+            .location
+            .clone()
+            .filter(|location| !location.is_empty()),
+        tags,
+    }
+}
+
+/// Returns the color of the first tag with a color, or of the priority
+pub fn color_id(conf: &ColorsConf, tags: &[String], priority: Option<char>) -> Option<String> {
+    tags.iter()
+        .find_map(|tag| conf.tags.get(tag))
+        .or_else(|| priority.and_then(|priority| conf.priorities.get(&priority)))
+        .cloned()
+}
+
+/// Updates tags and priority, so that they map to the color of an event
+///
+/// Tags with other colors are removed. Colors without tag or priority are
+/// ignored.
+pub fn apply_color_id(
+    conf: &ColorsConf,
+    color: &str,
+    tags: &mut Vec<String>,
+    priority: &mut Option<char>,
+) {
+    if color_id(conf, tags, *priority).as_deref() == Some(color) {
+        return;
+    }
+
+    let tag = conf.tags.iter().find(|(_, c)| *c == color);
+    let new_priority = conf.priorities.iter().find(|(_, c)| *c == color);
+
+    if tag.is_none() && new_priority.is_none() {
+        return;
+    }
+
+    tags.retain(|tag| conf.tags.get(tag).is_none_or(|c| c == color));
+
+    if let Some((tag, _)) = tag {
+        if !tags.iter().any(|tag| conf.tags.contains_key(tag)) {
+            tags.push(tag.clone());
+        }
+    } else if let Some((new_priority, _)) = new_priority {
+        *priority = Some(*new_priority);
+    }
+}
+
+/// Google Calendar allows at most five reminders per event
+const MAX_REMINDERS: usize = 5;
+
+/// Google Calendar rejects reminders more than four weeks before an event
+const MAX_REMINDER_MINUTES: u32 = 60 * 24 * 7 * 4;
+
+/// Converts a duration like `3d` into minutes
+///
+/// Durations without unit are in minutes.
+fn parse_minutes(text: &str) -> Option<u32> {
+    let (value, unit) = match text.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => text.split_at(i),
+        None => (text, "m"),
+    };
+
+    let minutes = match unit {
+        "m" | "min" => 1,
+        "h" => 60,
+        "d" => 60 * 24,
+        "w" => 60 * 24 * 7,
+        _ => return None,
+    };
+
+    value.parse::<u32>().ok()?.checked_mul(minutes)
+}
+
+/// Converts the duration of a reminder into minutes, ignoring reminders
+/// Google Calendar doesn't support
+fn reminder_minutes(text: &str) -> Option<u32> {
+    match parse_minutes(text)? {
+        minutes if minutes > MAX_REMINDER_MINUTES => {
+            warn!(
+                "Reminder of {} is more than four weeks before the event. Ignoring.",
+                text
+            );
+            None
+        }
+        minutes => Some(minutes),
+    }
+}
+
+fn format_minutes(minutes: u32) -> String {
+    match minutes {
+        0 => "0m".into(),
+        _ if minutes.is_multiple_of(60 * 24 * 7) => format!("{}w", minutes / (60 * 24 * 7)),
+        _ if minutes.is_multiple_of(60 * 24) => format!("{}d", minutes / (60 * 24)),
+        _ if minutes.is_multiple_of(60) => format!("{}h", minutes / 60),
+        _ => format!("{}m", minutes),
+    }
+}
+
+/// Converts the warning period of a deadline, e.g. `-3d`, into minutes
+pub fn warning_minutes(delay: &str) -> Option<u32> {
+    reminder_minutes(delay.trim_start_matches('-'))
+}
+
+/// Parses a list of reminders, e.g. `popup 10m, email 1d`
+///
+/// The method defaults to popup.
+pub fn parse_reminders(text: &str) -> Vec<EventReminder> {
+    text.split(',')
+        .filter_map(|reminder| {
+            let mut words = reminder.split_whitespace().rev();
+            let minutes = reminder_minutes(words.next()?)?;
+            let method = words.next().unwrap_or("popup");
+
+            Some(EventReminder {
+                method: method.into(),
+                minutes,
+            })
+        })
+        .collect()
+}
+
+pub fn format_reminders(reminders: &[EventReminder]) -> String {
+    reminders
+        .iter()
+        .map(|reminder| format!("{} {}", reminder.method, format_minutes(reminder.minutes)))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Writes the reminders of a headline and its deadline warning period into an event
+pub fn write_reminders(
+    event: &mut Event,
+    conf: &FieldsConf,
+    reminders: &[EventReminder],
+    warning: Option<u32>,
+) {
+    let mut overrides: Vec<_> = warning
+        .map(|minutes| EventReminder {
+            method: "popup".into(),
+            minutes,
+        })
+        .into_iter()
+        .chain(reminders.iter().cloned())
+        .collect();
+
+    overrides.truncate(MAX_REMINDERS);
+
+    if conf.reminders.is_some() || !overrides.is_empty() {
+        event.reminders = Some(Reminders {
+            use_default: overrides.is_empty(),
+            overrides,
+        });
+    }
+}
+
+/// Reads the reminders of an event, separating the reminder of the
+/// deadline warning period, if any
+///
+/// Returns the other reminders, and whether the warning period is kept.
+pub fn read_reminders(event: &Event, warning: Option<u32>) -> (Vec<EventReminder>, bool) {
+    let mut overrides = match &event.reminders {
+        Some(reminders) if !reminders.use_default => reminders.overrides.clone(),
+        _ => Vec::new(),
+    };
+
+    let warning = warning.and_then(|minutes| {
+        overrides
+            .iter()
+            .position(|reminder| reminder.method == "popup" && reminder.minutes == minutes)
+    });
+
+    if let Some(i) = warning {
+        overrides.remove(i);
+    }
+
+    (overrides, warning.is_some())
+}
+
+/// Parses a list of attendees, e.g. `alice@example.com (accepted), bob@example.com`
+pub fn parse_attendees(text: &str) -> Vec<Attendee> {
+    text.split(',')
+        .map(str::trim)
+        .filter(|attendee| !attendee.is_empty())
+        .map(|attendee| {
+            match attendee
+                .strip_suffix(')')
+                .and_then(|rest| rest.rsplit_once('('))
+            {
+                Some((email, status)) => Attendee {
+                    email: email.trim().into(),
+                    response_status: Some(status.trim().into()),
+                },
+                None => Attendee {
+                    email: attendee.into(),
+                    response_status: None,
+                },
+            }
+        })
+        .collect()
+}
+
+/// Renders a list of attendees with their response status, unless they
+/// haven't responded yet
+pub fn format_attendees(attendees: &[Attendee]) -> String {
+    attendees
+        .iter()
+        .map(|attendee| match attendee.response_status.as_deref() {
+            Some("needsAction") | None => attendee.email.clone(),
+            Some(status) => format!("{} ({})", attendee.email, status),
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    fn conf(tags: TagsMapping) -> FieldsConf {
+        FieldsConf {
+            tags,
+            ..Default::default()
+        }
+    }
+
+    fn round_trip(conf: &FieldsConf, body: &str, tags: &[&str]) -> (Event, Fields) {
+        let tags: Vec<String> = tags.iter().map(|tag| tag.to_string()).collect();
+        let mut event = Event::default();
+
+        write_fields(&mut event, conf, body, Some("Room 1"), &tags);
+        let fields = read_fields(&event, conf);
+
+        (event, fields)
+    }
+
+    #[test]
+    fn tags_in_description() {
+        let conf = conf(TagsMapping::Description);
+        let (event, fields) = round_trip(&conf, "Agenda\n\nNotes", &["work", "meeting"]);
+
+        assert_eq!(
+            event.description.as_deref(),
+            Some("Agenda\n\nNotes\n\nTags: :work:meeting:")
+        );
+        assert_eq!(fields.body.as_deref(), Some("Agenda\n\nNotes"));
+        assert_eq!(fields.location.as_deref(), Some("Room 1"));
+        assert_eq!(fields.tags, Some(vec!["work".into(), "meeting".into()]));
+
+        let (event, fields) = round_trip(&conf, "Agenda", &[]);
+
+        assert_eq!(event.description.as_deref(), Some("Agenda"));
+        assert_eq!(fields.body.as_deref(), Some("Agenda"));
+        assert_eq!(fields.tags, Some(Vec::new()));
+    }
+
+    #[test]
+    fn tags_in_extended_property() {
+        let conf = conf(TagsMapping::Extended);
+        let (event, fields) = round_trip(&conf, "Agenda", &["work"]);
+
+        assert_eq!(event.description.as_deref(), Some("Agenda"));
+        assert_eq!(fields.body.as_deref(), Some("Agenda"));
+        assert_eq!(fields.tags, Some(vec!["work".into()]));
+    }
+
+    #[test]
+    fn tags_not_stored() {
+        let conf = FieldsConf {
+            description: false,
+            location: None,
+            ..conf(TagsMapping::None)
+        };
+        let (event, fields) = round_trip(&conf, "Agenda", &["work"]);
+
+        assert!(event.description.is_none());
+        assert!(event.location.is_none());
+        assert!(event.extended_properties.is_none());
+        assert!(fields.body.is_none());
+        assert!(fields.tags.is_none());
+    }
+
+    fn colors() -> ColorsConf {
+        ColorsConf {
+            tags: BTreeMap::from([("work".into(), "1".into()), ("home".into(), "2".into())]),
+            priorities: BTreeMap::from([('A', "11".into())]),
+        }
+    }
+
+    fn apply(color: &str, tags: &[&str], priority: Option<char>) -> (Vec<String>, Option<char>) {
+        let mut tags = tags.iter().map(|tag| tag.to_string()).collect();
+        let mut priority = priority;
+
+        apply_color_id(&colors(), color, &mut tags, &mut priority);
+
+        (tags, priority)
+    }
+
+    #[test]
+    fn color_of_tags() {
+        assert_eq!(
+            color_id(&colors(), &["misc".into(), "home".into()], Some('A')),
+            Some("2".into())
+        );
+        assert_eq!(color_id(&colors(), &[], Some('A')), Some("11".into()));
+        assert_eq!(color_id(&colors(), &[], Some('B')), None);
+    }
+
+    #[test]
+    fn apply_color_of_other_tag() {
+        // the tag with the color of the event wins over conflicting ones
+        assert_eq!(
+            apply("2", &["work", "home", "misc"], None),
+            (vec!["home".into(), "misc".into()], None)
+        );
+        assert_eq!(
+            apply("2", &["work", "misc"], None),
+            (vec!["misc".into(), "home".into()], None)
+        );
+    }
+
+    #[test]
+    fn apply_color_of_priority() {
+        assert_eq!(
+            apply("11", &["work", "misc"], Some('B')),
+            (vec!["misc".into()], Some('A'))
+        );
+    }
+
+    #[test]
+    fn apply_unknown_color() {
+        assert_eq!(
+            apply("5", &["work"], Some('B')),
+            (vec!["work".into()], Some('B'))
+        );
+    }
+
+    fn reminder(method: &str, minutes: u32) -> EventReminder {
+        EventReminder {
+            method: method.into(),
+            minutes,
+        }
+    }
+
+    #[test]
+    fn reminders() {
+        let reminders = parse_reminders("popup 10m, email 1d,2h, 0");
+
+        assert_eq!(
+            reminders,
+            vec![
+                reminder("popup", 10),
+                reminder("email", 60 * 24),
+                reminder("popup", 120),
+                reminder("popup", 0),
+            ]
+        );
+        assert_eq!(
+            format_reminders(&reminders),
+            "popup 10m, email 1d, popup 2h, popup 0m"
+        );
+    }
+
+    #[test]
+    fn long_reminders() {
+        assert_eq!(parse_reminders("4w"), vec![reminder("popup", 40320)]);
+        assert_eq!(format_reminders(&[reminder("popup", 40320)]), "popup 4w");
+        assert!(parse_reminders("5w, popup 40321").is_empty());
+        assert_eq!(warning_minutes("-3d"), Some(3 * 60 * 24));
+        assert_eq!(warning_minutes("-5w"), None);
+    }
+
+    #[test]
+    fn overflowing_reminders() {
+        assert!(parse_reminders("4294967295h, 99999999999").is_empty());
+        assert!(parse_reminders("popup 3x").is_empty());
+    }
+
+    #[test]
+    fn attendees() {
+        let attendees = parse_attendees("alice@example.com (accepted), bob@example.com, ");
+
+        assert_eq!(attendees.len(), 2);
+        assert_eq!(attendees[0].email, "alice@example.com");
+        assert_eq!(attendees[0].response_status.as_deref(), Some("accepted"));
+        assert_eq!(attendees[1].email, "bob@example.com");
+        assert_eq!(attendees[1].response_status, None);
+
+        assert_eq!(
+            format_attendees(&attendees),
+            "alice@example.com (accepted), bob@example.com"
+        );
+    }
+
+    #[test]
+    fn attendees_without_response() {
+        let attendees = [Attendee {
+            email: "carol@example.com".into(),
+            response_status: Some("needsAction".into()),
+        }];
+
+        assert_eq!(format_attendees(&attendees), "carol@example.com");
+    }
+}
@@ -1,16 +1,1602 @@
 pub mod api;
 pub mod auth;
+pub mod batch;
+pub mod client;
+pub mod convert;
+pub mod fields;
 pub mod models;
+pub mod repeater;
+pub mod service_account;
+pub mod state;
+pub mod token;
 
-use chrono::{DateTime, Utc};
-use orgize::Org;
+use chrono::{DateTime, Datelike, Duration, Local, NaiveDate, Utc};
+use chrono_tz::Tz;
+use isahc::http::StatusCode;
+use log::{debug, error, info, trace, warn};
+use orgize::{
+    elements::{Element, Planning, Timestamp, Title},
+    export::{DefaultOrgHandler, OrgHandler},
+    indextree::NodeEdge,
+    Headline, Org, ParseConfig,
+};
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{stdin, BufRead};
+use std::iter;
 
-pub async fn sync() {}
+use crate::{
+    conf::{
+        CalendarConf, Conf, ConflictPolicy, DeletePolicy, GoogleCalendarConf,
+        GoogleCalendarGlobalConf, SyncDirection, TagsMapping,
+    },
+    error::{Error, Result},
+    google::{
+        batch::Batch,
+        client::Client,
+        convert::{
+            event_start, event_timestamp, same_time, time_zone, to_datetime, to_utc, TimeRange,
+        },
+        fields::{
+            apply_color_id, color_id, format_attendees, format_reminders, parse_attendees,
+            parse_reminders, read_fields, read_reminders, warning_minutes, write_fields,
+            write_reminders,
+        },
+        models::{Attendee, CalendarListEntry, Event, EventReminder},
+        repeater::{Cookies, Repeater, DEADLINE_PROPERTY, SCHEDULED_PROPERTY},
+        state::{CalendarState, SyncState},
+    },
+};
+
+pub async fn sync(conf: &Conf) -> Result<()> {
+    let global = match &conf.google_calendar {
+        Some(global) => global,
+        None => {
+            warn!("Google Calendar is not configured. Skipping.");
+            return Ok(());
+        }
+    };
+
+    let mut state = SyncState::load(global)?;
+    let mut client = Client::new(global.clone());
+    let mut calendar_list = None;
+
+    for file in &conf.files {
+        if let Some(google_calendar) = &file.google_calendar {
+            let tz = time_zone(google_calendar, global)?;
+
+            let mut google_calendar = google_calendar.clone();
+            resolve_calendars(&mut client, &mut google_calendar, &mut calendar_list).await?;
+
+            for calendar in google_calendar.calendars() {
+                let calendar_state = state.calendar(&file.path, &calendar.calendar);
+
+                sync_file(
+                    &mut client,
+                    &file.path,
+                    &google_calendar,
+                    &calendar,
+                    tz,
+                    calendar_state,
+                )
+                .await?;
+
+                state.save(global)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Replaces the calendar names in `conf` with their ids
+///
+/// The calendar list is only fetched if a name has to be resolved, and kept
+/// in `calendar_list` for the next files.
+async fn resolve_calendars(
+    client: &mut Client,
+    conf: &mut GoogleCalendarConf,
+    calendar_list: &mut Option<Vec<CalendarListEntry>>,
+) -> Result<()> {
+    let calendars = iter::once(&mut conf.calendar).chain(
+        conf.calendars
+            .iter_mut()
+            .map(|calendar| &mut calendar.calendar),
+    );
+
+    for calendar in calendars {
+        // ids are email addresses, or "primary"
+        if calendar.is_empty() || calendar == "primary" || calendar.contains('@') {
+            continue;
+        }
+
+        if calendar_list.is_none() {
+            *calendar_list = Some(api::list_calendars(client).await?);
+        }
+
+        let mut entries = calendar_list
+            .iter()
+            .flatten()
+            .filter(|entry| entry.id == *calendar || entry.name() == calendar);
+
+        match (entries.next(), entries.next()) {
+            (Some(entry), None) => {
+                debug!("Resolved calendar \"{}\" to {}.", calendar, entry.id);
+                *calendar = entry.id.clone();
+            }
+            (Some(entry), Some(_)) => {
+                warn!(
+                    "Several calendars are named \"{}\". Using {}.",
+                    calendar, entry.id
+                );
+                *calendar = entry.id.clone();
+            }
+            (None, _) => warn!("Calendar \"{}\" not found in the calendar list.", calendar),
+        }
+    }
+
+    Ok(())
+}
+
+/// Prints the calendars of the user
+pub async fn print_calendars(conf: &GoogleCalendarGlobalConf) -> Result<()> {
+    let mut client = Client::new(conf.clone());
+    let calendars = api::list_calendars(&mut client).await?;
+
+    let width = calendars
+        .iter()
+        .map(|calendar| calendar.id.len())
+        .max()
+        .unwrap_or_default()
+        .max(2)
+        + 2;
+    let name_width = calendars
+        .iter()
+        .map(|calendar| calendar.name().chars().count())
+        .max()
+        .unwrap_or_default()
+        .max(7)
+        + 2;
+
+    println!(
+        "{:<width$}{:<name_width$}{:<16}TIME ZONE",
+        "ID",
+        "SUMMARY",
+        "ACCESS ROLE",
+        width = width,
+        name_width = name_width
+    );
+    for calendar in calendars {
+        println!(
+            "{:<width$}{:<name_width$}{:<16}{}",
+            calendar.id,
+            calendar.name(),
+            calendar.access_role,
+            calendar.time_zone.as_deref().unwrap_or_default(),
+            width = width,
+            name_width = name_width
+        );
+    }
+
+    Ok(())
+}
+
+/// Prints the palette of event colors
+pub async fn print_colors(conf: &GoogleCalendarGlobalConf) -> Result<()> {
+    let mut client = Client::new(conf.clone());
+    let colors = api::get_colors(&mut client).await?;
+
+    let mut colors: Vec<_> = colors.event.into_iter().collect();
+    colors.sort_by_key(|(id, _)| id.parse::<u32>().unwrap_or(u32::MAX));
+
+    println!("{:<4}{:<12}FOREGROUND", "ID", "BACKGROUND");
+    for (id, color) in colors {
+        println!("{:<4}{:<12}{}", id, color.background, color.foreground);
+    }
+
+    Ok(())
+}
+
+async fn sync_file(
+    client: &mut Client,
+    path: &str,
+    conf: &GoogleCalendarConf,
+    calendar: &CalendarConf,
+    tz: Tz,
+    state: &mut CalendarState,
+) -> Result<()> {
+    info!(
+        "Synchronizing \"{}\" with calendar \"{}\".",
+        path, calendar.calendar
+    );
+
+    let text = repeater::extract(&fs::read_to_string(path)?);
+    let mut org = Org::parse_custom(&text, &parse_config(conf));
+
+    let now = Utc::now();
+    let time_min = now - Duration::days(conf.up_days.into());
+    let time_max = now + Duration::days(conf.down_days.into());
+
+    let headlines = filter_headlines_by_scheduled(&org, conf, calendar, time_min, time_max, tz)?;
+
+    debug!("Found {} scheduled headline(s).", headlines.len());
+
+    let today = Local::now().date_naive();
+    let sync_token = match (&state.sync_token, state.sync_token_date) {
+        (Some(sync_token), Some(date)) if date == today => Some(sync_token.as_str()),
+        _ => None,
+    };
+
+    let events =
+        api::list_events(client, &calendar.calendar, time_min, time_max, sync_token).await?;
+
+    debug!("Fetched {} changed event(s).", events.items.len());
+
+    state.sync_token = events.next_sync_token;
+    state.sync_token_date = Some(today);
+
+    let events = collapse_recurring(client, calendar, events.items, tz).await?;
+
+    let mut changed = false;
+    let mut failed = Vec::new();
+    let mut batch = Batch::new(&calendar.calendar, conf.send_updates);
+
+    for (index, headline) in headlines.iter().enumerate() {
+        let id = match &headline.event_id {
+            Some(id) => id,
+            None if calendar.direction.pushes() => {
+                batch.insert(Write::Insert { index }, headline.to_event(conf));
+                continue;
+            }
+            None => continue,
+        };
+
+        let event = events.iter().find(|event| event.id.as_ref() == Some(id));
+
+        if !event.is_some_and(Event::is_cancelled) {
+            match update_linked(
+                client, &mut org, &mut batch, headline, id, event, conf, calendar, state,
+            )
+            .await
+            {
+                Ok(updated) => changed |= updated,
+                Err(err) => {
+                    error!("Failed to synchronize \"{}\": {}", headline.title, err);
+
+                    failed.push(id.clone());
+                }
+            }
+        }
+    }
+
+    let (deleted, pending) = sync_deletions(
+        &mut org, &mut batch, &headlines, &events, conf, calendar, state,
+    )?;
+    changed |= deleted;
+
+    if conf.append_new && calendar.direction.pulls() {
+        let event_ids: HashSet<String> = org
+            .headlines()
+            .filter_map(|headline| headline.title(&org).properties.get(conf.property.as_str()))
+            .map(|id| id.to_string())
+            .collect();
+
+        let new_events: Vec<_> = events
+            .iter()
+            .filter(|event| match &event.id {
+                Some(id) => {
+                    !event.is_cancelled()
+                        && !event_ids.contains(id)
+                        && !event
+                            .recurring_event_id
+                            .as_ref()
+                            .is_some_and(|id| event_ids.contains(id))
+                        && event_in_window(event, time_min, time_max, tz)
+                }
+                None => false,
+            })
+            .collect();
+
+        if !new_events.is_empty() {
+            let title = calendar
+                .append_headline
+                .as_ref()
+                .unwrap_or(&conf.append_headline);
+            let parent = find_or_create_headline(&mut org, title)?;
+
+            for event in new_events {
+                info!("Appending event \"{}\".", event.summary);
+
+                append_event(&mut org, parent, event, conf, calendar, tz)?;
+            }
+
+            changed = true;
+        }
+    }
+
+    let synced_etags = state.etags.clone();
+
+    // later compared with the ETags of the events to detect their changes
+    for event in &events {
+        if let Some(id) = &event.id {
+            if event.is_cancelled() {
+                state.etags.remove(id);
+            } else if !event.etag.is_empty() {
+                state.etags.insert(id.clone(), event.etag.clone());
+            }
+        }
+    }
+
+    let mut resolve_conflicts = true;
+
+    while !batch.is_empty() {
+        let results = batch.execute(client).await;
+        batch = Batch::new(&calendar.calendar, conf.send_updates);
+
+        let (written, conflicts, errors) = apply_writes(
+            &mut org,
+            &headlines,
+            results,
+            conf,
+            calendar,
+            state,
+            resolve_conflicts,
+        );
+        changed |= written;
+        failed.extend(errors);
+
+        for write in conflicts {
+            let id = write.event_id().map(String::from);
+
+            match resolve_conflict(
+                client, &mut org, &mut batch, &headlines, write, conf, calendar, state,
+            )
+            .await
+            {
+                Ok(resolved) => changed |= resolved,
+                Err(err) => {
+                    let id = id.unwrap_or_default();
+
+                    error!("Failed to resolve the conflict of event {}: {}", id, err);
+
+                    failed.push(id);
+                }
+            }
+        }
+
+        // writes rejected again are reported as failures
+        resolve_conflicts = false;
+    }
+
+    let previous = std::mem::take(&mut state.headlines);
+    for headline in filter_headlines_by_scheduled(&org, conf, calendar, time_min, time_max, tz)? {
+        if let Some(id) = &headline.event_id {
+            let snapshot = serde_json::to_string(&headline.to_event(conf))?;
+            state.headlines.insert(id.clone(), snapshot);
+        }
+    }
+    state.headlines.extend(pending);
+
+    // keeps the previous snapshots and ETags, so that failed writes are
+    // tried again, and skipped conflicts are found again
+    for id in failed {
+        match synced_etags.get(&id) {
+            Some(etag) => state.etags.insert(id.clone(), etag.clone()),
+            None => state.etags.remove(&id),
+        };
+        match previous.get(&id) {
+            Some(snapshot) => state.headlines.insert(id, snapshot.clone()),
+            None => state.headlines.remove(&id),
+        };
+    }
+
+    let CalendarState {
+        headlines, etags, ..
+    } = state;
+    etags.retain(|id, _| headlines.contains_key(id));
+
+    state.last_sync = Some(Utc::now());
+
+    if !changed {
+        return Ok(());
+    }
+
+    trace!("Saving \"{}\".", path);
+
+    let mut content = Vec::new();
+    org.write_org(&mut content)?;
+    fs::write(path, repeater::restore(&String::from_utf8_lossy(&content)))?;
+
+    Ok(())
+}
+
+/// Replaces the instances of recurring events with the recurring events
+///
+/// The start of a recurring event is moved to its first instance, so that
+/// it lines up with the repeating org timestamp. The instances of
+/// recurrences which can't be expressed as org repeaters are kept and
+/// synchronized as single events.
+async fn collapse_recurring(
+    client: &mut Client,
+    calendar: &CalendarConf,
+    events: Vec<Event>,
+    tz: Tz,
+) -> Result<Vec<Event>> {
+    let mut recurring: Vec<(String, Option<Event>)> = Vec::new();
+
+    for id in events
+        .iter()
+        .filter_map(|event| event.recurring_event_id.as_ref())
+    {
+        if recurring.iter().any(|(r, _)| r == id) {
+            continue;
+        }
+
+        let event = match api::get_event(client, &calendar.calendar, id).await {
+            Ok(event) if event.is_cancelled() || event_repeater(&event, tz).is_some() => {
+                Some(event)
+            }
+            Ok(event) => {
+                warn!(
+                    "Recurrence of \"{}\" can't be expressed as an org repeater. \
+                     Synchronizing its instances as single events.",
+                    event.summary
+                );
+                None
+            }
+            Err(Error::GoogleApi { status, .. }) if status == StatusCode::NOT_FOUND => {
+                debug!("Recurring event {} not found.", id);
+                None
+            }
+            Err(err) => return Err(err),
+        };
+
+        recurring.push((id.clone(), event));
+    }
+
+    let mut collapsed = Vec::new();
+    let mut first_instances: HashMap<String, Event> = HashMap::new();
+
+    for event in events {
+        let id = event.recurring_event_id.clone().filter(|id| {
+            recurring
+                .iter()
+                .any(|(r, event)| r == id && event.is_some())
+        });
+
+        match id {
+            Some(id) if !event.is_cancelled() => match first_instances.get(&id) {
+                Some(first) if event_start(first, tz) <= event_start(&event, tz) => (),
+                _ => {
+                    first_instances.insert(id, event);
+                }
+            },
+            Some(_) => (),
+            None => collapsed.push(event),
+        }
+    }
+
+    for (id, event) in recurring {
+        if let Some(mut event) = event {
+            if let Some(first) = first_instances.remove(&id) {
+                event.start = first.start;
+                event.end = first.end;
+            }
+
+            collapsed.push(event);
+        }
+    }
+
+    Ok(collapsed)
+}
+
+/// Propagates deletions between org and Google Calendar
+///
+/// Events to delete are added to the batch. Returns whether the org file
+/// was changed, and the snapshots of deleted headlines whose events were
+/// kept because of `max_deletions`. Skipped deletions drop the sync token,
+/// so that cancelled events are listed again.
+fn sync_deletions(
+    org: &mut Org<'_>,
+    batch: &mut Batch<Write>,
+    headlines: &[ScheduledHeadline],
+    events: &[Event],
+    conf: &GoogleCalendarConf,
+    calendar: &CalendarConf,
+    state: &mut CalendarState,
+) -> Result<(bool, HashMap<String, String>)> {
+    let is_cancelled = |id: &str| {
+        events
+            .iter()
+            .any(|event| event.id.as_deref() == Some(id) && event.is_cancelled())
+    };
+
+    // headlines deleted from org, or archived
+    let mut archived = Vec::new();
+    let mut event_ids = HashSet::new();
+    for headline in org.headlines() {
+        let title = headline.title(org);
+        if let Some(id) = title.properties.get(conf.property.as_str()) {
+            if title.is_archived() && belongs_to(title, conf, calendar) {
+                archived.push((headline, id.to_string()));
+            } else {
+                event_ids.insert(id.to_string());
+            }
+        }
+    }
+
+    let removed: Vec<_> = state
+        .headlines
+        .keys()
+        .filter(|id| !event_ids.contains(*id) && !archived.iter().any(|(_, a)| a == *id))
+        .filter(|id| !is_cancelled(id))
+        .cloned()
+        .collect();
+
+    // events deleted from calendar
+    let cancelled: Vec<_> = headlines
+        .iter()
+        .filter(|headline| match &headline.event_id {
+            Some(id) => is_cancelled(id) && !is_marked_deleted(org, headline.headline, conf),
+            None => false,
+        })
+        .filter(|_| calendar.direction.pulls())
+        .collect();
+
+    let count = archived.len() + removed.len() + cancelled.len();
+
+    if count > conf.max_deletions {
+        error!(
+            "Refusing to delete {} items, which exceeds max_deletions ({}). Skipping deletions.",
+            count, conf.max_deletions
+        );
+
+        // the next synchronization lists all events again
+        state.sync_token = None;
+
+        let pending = removed
+            .into_iter()
+            .filter_map(|id| Some((id.clone(), state.headlines.get(&id)?.clone())))
+            .collect();
+
+        return Ok((false, pending));
+    }
+
+    let mut changed = false;
+
+    for (headline, id) in archived {
+        if !is_cancelled(&id) && calendar.direction.pushes() {
+            info!(
+                "Deleting event for archived \"{}\".",
+                headline.title(org).raw
+            );
+
+            // the event id is removed once the event is deleted
+            let etag = state.etags.get(&id).map(String::as_str);
+            batch.delete(
+                Write::DeleteArchived {
+                    headline,
+                    id: id.clone(),
+                },
+                &id,
+                etag,
+            );
+            continue;
+        }
+
+        headline
+            .title_mut(org)
+            .properties
+            .remove(conf.property.as_str());
+
+        changed = true;
+    }
+
+    for id in removed.into_iter().filter(|_| calendar.direction.pushes()) {
+        info!("Deleting event {}.", id);
+
+        let etag = state.etags.get(&id).map(String::as_str);
+        batch.delete(Write::DeleteRemoved { id: id.clone() }, &id, etag);
+    }
+
+    for headline in cancelled {
+        info!("Event for \"{}\" was deleted.", headline.title);
+
+        mark_deleted(org, headline.headline, conf);
+
+        changed = true;
+    }
+
+    Ok((changed, HashMap::new()))
+}
+
+/// Origin of a batched write
+enum Write {
+    /// Event for a headline without event, by its position in the
+    /// scheduled headlines
+    Insert {
+        index: usize,
+    },
+    Patch {
+        id: String,
+        title: String,
+    },
+    /// Event of an archived headline
+    DeleteArchived {
+        headline: Headline,
+        id: String,
+    },
+    /// Event of a headline removed from org
+    DeleteRemoved {
+        id: String,
+    },
+}
+
+impl Write {
+    /// Returns the id of the written event, unless it is inserted
+    fn event_id(&self) -> Option<&str> {
+        match self {
+            Write::Insert { .. } => None,
+            Write::Patch { id, .. }
+            | Write::DeleteArchived { id, .. }
+            | Write::DeleteRemoved { id } => Some(id),
+        }
+    }
+}
+
+/// Applies the results of batched writes to org
+///
+/// Failed writes are logged without stopping the synchronization. Writes
+/// rejected because the event was changed meanwhile are returned as
+/// conflicts, if `resolve_conflicts` is set.
+///
+/// Returns whether the org file was changed, the conflicts, and the ids of
+/// the events which couldn't be updated or deleted.
+fn apply_writes(
+    org: &mut Org<'_>,
+    headlines: &[ScheduledHeadline],
+    results: Vec<(Write, Result<Option<Event>>)>,
+    conf: &GoogleCalendarConf,
+    calendar: &CalendarConf,
+    state: &mut CalendarState,
+    resolve_conflicts: bool,
+) -> (bool, Vec<Write>, Vec<String>) {
+    let mut changed = false;
+    let mut conflicts = Vec::new();
+    let mut failed = Vec::new();
+
+    for (write, result) in results {
+        match (write, result) {
+            (write, Err(Error::GoogleApi { status, .. }))
+                if resolve_conflicts && status == StatusCode::PRECONDITION_FAILED =>
+            {
+                conflicts.push(write);
+            }
+            (Write::Insert { index }, Ok(event)) => {
+                let headline = &headlines[index];
+
+                if let Some(Event {
+                    id: Some(id), etag, ..
+                }) = event
+                {
+                    info!("Created event for \"{}\".", headline.title);
+
+                    state.etags.insert(id.clone(), etag);
+
+                    let properties = &mut headline.headline.title_mut(org).properties;
+                    properties.insert(conf.property.clone().into(), id.into());
+                    properties.insert(
+                        conf.calendar_property.clone().into(),
+                        calendar.calendar.clone().into(),
+                    );
+
+                    changed = true;
+                }
+            }
+            (Write::Insert { index }, Err(err)) => {
+                error!(
+                    "Failed to create event for \"{}\": {}",
+                    headlines[index].title, err
+                );
+            }
+            (Write::Patch { id, .. }, Ok(event)) => {
+                if let Some(event) = event {
+                    state.etags.insert(id, event.etag);
+                }
+            }
+            (Write::DeleteRemoved { id }, Ok(_)) => {
+                state.etags.remove(&id);
+            }
+            (Write::Patch { id, title }, Err(err)) => {
+                error!("Failed to update event for \"{}\": {}", title, err);
+
+                failed.push(id);
+            }
+            (Write::DeleteArchived { headline, id }, Ok(_)) => {
+                state.etags.remove(&id);
+
+                headline
+                    .title_mut(org)
+                    .properties
+                    .remove(conf.property.as_str());
+
+                changed = true;
+            }
+            (Write::DeleteArchived { headline, .. }, Err(err)) => {
+                error!(
+                    "Failed to delete event for archived \"{}\": {}",
+                    headline.title(org).raw,
+                    err
+                );
+            }
+            (Write::DeleteRemoved { id }, Err(err)) => {
+                error!("Failed to delete event {}: {}", id, err);
+
+                failed.push(id);
+            }
+        }
+    }
+
+    (changed, conflicts, failed)
+}
+
+/// Resolves a write rejected because the event was changed since it was
+/// fetched
+///
+/// The event is fetched again, and the conflict is resolved as if it was
+/// found when listing events, so that changes in Google Calendar aren't
+/// overwritten blindly. Returns `true` if the org file was changed.
+#[allow(clippy::too_many_arguments)]
+async fn resolve_conflict(
+    client: &mut Client,
+    org: &mut Org<'_>,
+    batch: &mut Batch<Write>,
+    headlines: &[ScheduledHeadline],
+    write: Write,
+    conf: &GoogleCalendarConf,
+    calendar: &CalendarConf,
+    state: &CalendarState,
+) -> Result<bool> {
+    let id = match write.event_id() {
+        Some(id) => id.to_string(),
+        None => return Ok(false),
+    };
+
+    let event = api::get_event(client, &calendar.calendar, &id).await?;
+
+    let title = match &write {
+        Write::Patch { title, .. } => title.clone(),
+        Write::DeleteArchived { headline, .. } => headline.title(org).raw.to_string(),
+        _ => event.summary.clone(),
+    };
+
+    info!("Event for \"{}\" was changed meanwhile.", title);
+
+    if let Write::Patch { .. } = write {
+        let headline = headlines
+            .iter()
+            .find(|headline| headline.event_id.as_ref() == Some(&id));
+
+        return match headline {
+            // deleted events are handled in the next synchronization
+            Some(headline) if !event.is_cancelled() => {
+                update_linked(
+                    client,
+                    org,
+                    batch,
+                    headline,
+                    &id,
+                    Some(&event),
+                    conf,
+                    calendar,
+                    state,
+                )
+                .await
+            }
+            _ => Ok(false),
+        };
+    }
+
+    let prefer_org = event.is_cancelled()
+        || match (calendar.direction, conf.conflict) {
+            (SyncDirection::Push, _) | (_, ConflictPolicy::Org) => true,
+            (_, ConflictPolicy::Calendar) => false,
+            (_, ConflictPolicy::Ask) => ask_prefer_org(&title)?,
+        };
+
+    if prefer_org {
+        batch.delete(write, &id, Some(&event.etag));
+        return Ok(false);
+    }
+
+    info!("Keeping event \"{}\".", event.summary);
+
+    // the event is unlinked, and can be appended again
+    if let Write::DeleteArchived { headline, .. } = write {
+        headline
+            .title_mut(org)
+            .properties
+            .remove(conf.property.as_str());
+
+        return Ok(true);
+    }
+
+    Ok(false)
+}
+
+/// Propagates changes between a linked headline and its event
+///
+/// `event` is `None` if the event was not changed since the last
+/// synchronization, or is outside the window. Changes to the event are
+/// added to the batch.
+///
+/// Returns `true` if the headline was updated.
+#[allow(clippy::too_many_arguments)]
+async fn update_linked(
+    client: &mut Client,
+    org: &mut Org<'_>,
+    batch: &mut Batch<Write>,
+    headline: &ScheduledHeadline,
+    id: &str,
+    event: Option<&Event>,
+    conf: &GoogleCalendarConf,
+    calendar: &CalendarConf,
+    state: &CalendarState,
+) -> Result<bool> {
+    let local = headline.to_event(conf);
+
+    let org_changed = match (state.headlines.get(id), event) {
+        (Some(snapshot), _) => *snapshot != serde_json::to_string(&local)?,
+        (None, Some(event)) => !same_event(&local, event, headline.tz),
+        (None, None) => false,
+    };
+
+    // the ETag of an event changes with each of its changes
+    let calendar_changed = match (event, state.etags.get(id)) {
+        (Some(event), Some(etag)) => event.etag != *etag,
+        (Some(event), None) => !same_event(&local, event, headline.tz),
+        (None, _) => false,
+    };
+
+    let prefer_org = match (org_changed, calendar_changed, calendar.direction) {
+        (false, false, _) => return Ok(false),
+        (_, _, SyncDirection::Push) => true,
+        (_, _, SyncDirection::Pull) => false,
+        (true, false, _) => true,
+        (false, true, _) => false,
+        (true, true, _) => match conf.conflict {
+            ConflictPolicy::Org => true,
+            ConflictPolicy::Calendar => false,
+            ConflictPolicy::Ask => ask_prefer_org(&headline.title)?,
+        },
+    };
+
+    match event {
+        Some(event) if !prefer_org => {
+            info!("Updating headline \"{}\".", headline.title);
+
+            update_headline(org, headline, event, conf)?;
+
+            Ok(true)
+        }
+        None if !prefer_org => Ok(false),
+        _ => {
+            info!("Updating event for \"{}\".", headline.title);
+
+            let mut local = local;
+
+            let previous: Option<Event> = state
+                .headlines
+                .get(id)
+                .and_then(|snapshot| serde_json::from_str(snapshot).ok());
+            let previous = previous.as_ref().or(event);
+
+            let was_recurring = previous
+                .iter()
+                .chain(event.iter())
+                .any(|event| !event.recurrence.is_empty());
+
+            // the start of a recurring event is its first instance in the
+            // window, which must not become the start of the whole series
+            let keep_start = !local.recurrence.is_empty()
+                && was_recurring
+                && previous.is_some_and(|previous| {
+                    same_time(&previous.start, &local.start) && same_time(&previous.end, &local.end)
+                });
+
+            // keeps the time zone the event was created in
+            if local.start.date_time.is_some() && !keep_start {
+                let (start, end) = match event {
+                    Some(event) => (event.start.time_zone.clone(), event.end.time_zone.clone()),
+                    None => {
+                        let event = api::get_event(client, &calendar.calendar, id).await?;
+                        (event.start.time_zone, event.end.time_zone)
+                    }
+                };
+
+                local.start.time_zone = start.or(local.start.time_zone);
+                local.end.time_zone = end.or(local.end.time_zone);
+            }
+
+            batch.patch(
+                Write::Patch {
+                    id: id.into(),
+                    title: headline.title.clone(),
+                },
+                id,
+                event
+                    .map(|event| event.etag.as_str())
+                    .or_else(|| state.etags.get(id).map(String::as_str)),
+                patch_body(&local, conf, keep_start, was_recurring)?,
+            );
+
+            Ok(false)
+        }
+    }
+}
+
+/// Serializes an event for a patch
+///
+/// Empty fields are skipped when serializing events, but a patch keeps the
+/// fields it doesn't contain, so removed fields are cleared explicitly.
+fn patch_body(
+    event: &Event,
+    conf: &GoogleCalendarConf,
+    keep_start: bool,
+    was_recurring: bool,
+) -> Result<Value> {
+    let mut body = serde_json::to_value(event)?;
+
+    let description = conf.fields.description || conf.fields.tags == TagsMapping::Description;
+    if description && event.description.is_none() {
+        body["description"] = json!("");
+    }
+
+    if conf.fields.location.is_some() && event.location.is_none() {
+        body["location"] = json!("");
+    }
+
+    // null resets the color to the one of the calendar
+    let colors = !conf.colors.tags.is_empty() || !conf.colors.priorities.is_empty();
+    if colors && event.color_id.is_none() {
+        body["colorId"] = Value::Null;
+    }
+
+    if keep_start {
+        if let Value::Object(body) = &mut body {
+            body.remove("start");
+            body.remove("end");
+        }
+    }
+
+    if was_recurring && event.recurrence.is_empty() {
+        body["recurrence"] = json!([]);
+    }
+
+    Ok(body)
+}
+
+/// Asks the user which side to keep when both were changed
+fn ask_prefer_org(title: &str) -> Result<bool> {
+    info!(
+        "\"{}\" was changed both in org and in Google Calendar.",
+        title
+    );
+    info!("Keep which one? [o]rg or [c]alendar (press q to skip):");
+
+    for line in stdin().lock().lines() {
+        match line?.trim() {
+            "o" => return Ok(true),
+            "c" => return Ok(false),
+            "q" => return Err(Error::Conflict(format!("skipped \"{}\"", title))),
+            _ => continue,
+        }
+    }
+
+    // e.g. when running unattended
+    Err(Error::Conflict(format!(
+        "no answer for \"{}\", set \"conflict\" to \"org\" or \"calendar\" \
+         to synchronize without asking",
+        title
+    )))
+}
+
+/// Returns `true` if both events have the same title, time and repeater
+fn same_event(a: &Event, b: &Event, tz: Tz) -> bool {
+    a.summary == b.summary
+        && same_time(&a.start, &b.start)
+        && same_time(&a.end, &b.end)
+        && event_repeater(a, tz) == event_repeater(b, tz)
+}
+
+/// Converts the recurrence of an event into an org repeater, if expressible
+fn event_repeater(event: &Event, tz: Tz) -> Option<Repeater> {
+    if event.recurrence.is_empty() {
+        return None;
+    }
+
+    let start: NaiveDate = (&to_datetime(&event.start, tz).ok()??).into();
+
+    Repeater::from_recurrence(&event.recurrence, start.weekday())
+}
+
+/// Returns `true` if the event starts within the given window
+fn event_in_window(
+    event: &Event,
+    time_min: DateTime<Utc>,
+    time_max: DateTime<Utc>,
+    tz: Tz,
+) -> bool {
+    event_start(event, tz).is_some_and(|start| start >= time_min && start <= time_max)
+}
+
+/// A headline selected for synchronization
+pub struct ScheduledHeadline {
+    pub headline: Headline,
+    /// Raw title of the headline
+    pub title: String,
+    /// `SCHEDULED` timestamp, if any
+    pub scheduled: Option<TimeRange>,
+    /// `DEADLINE` timestamp, if any
+    pub deadline: Option<TimeRange>,
+    /// First active timestamp found in the title or section, if any
+    pub timestamp: Option<TimeRange>,
+    /// Repeater of the timestamp used as event time, if any
+    pub repeater: Option<Repeater>,
+    /// Section content of the headline, without drawers
+    pub body: String,
+    /// Value of the location property, if any
+    pub location: Option<String>,
+    pub tags: Vec<String>,
+    pub priority: Option<char>,
+    /// Value of the reminders property, if any
+    pub reminders: Vec<EventReminder>,
+    /// Warning period of the deadline used as event time, in minutes
+    pub warning: Option<u32>,
+    /// Value of the attendees property, if any
+    pub attendees: Vec<Attendee>,
+    /// Value of the event id property, if any
+    pub event_id: Option<String>,
+    /// Time zone of the org file
+    pub tz: Tz,
+}
+
+impl ScheduledHeadline {
+    /// Converts this headline into a new calendar event
+    pub fn to_event(&self, conf: &GoogleCalendarConf) -> Event {
+        let (start, end) = self.time().to_event_times(self.tz);
+
+        let mut event = Event {
+            summary: self.title.clone(),
+            recurrence: self.repeater.map(Repeater::to_rrule).into_iter().collect(),
+            color_id: color_id(&conf.colors, &self.tags, self.priority),
+            start,
+            end,
+            ..Default::default()
+        };
+
+        write_fields(
+            &mut event,
+            &conf.fields,
+            &self.body,
+            self.location.as_deref(),
+            &self.tags,
+        );
+
+        write_reminders(&mut event, &conf.fields, &self.reminders, self.warning);
+
+        if conf.fields.attendees.is_some() {
+            event.attendees = Some(self.attendees.clone());
+        }
+
+        event
+    }
+
+    /// Returns the timestamp used as event time, in order of preference:
+    /// `SCHEDULED`, `DEADLINE` and active timestamp.
+    pub fn time(&self) -> &TimeRange {
+        self.scheduled
+            .as_ref()
+            .or(self.deadline.as_ref())
+            .or(self.timestamp.as_ref())
+            .unwrap()
+    }
+}
 
 fn filter_headlines_by_scheduled(
     org: &Org,
+    conf: &GoogleCalendarConf,
+    calendar: &CalendarConf,
     time_min: DateTime<Utc>,
     time_max: DateTime<Utc>,
-    updated_min: Option<DateTime<Utc>>,
-) {
+    tz: Tz,
+) -> Result<Vec<ScheduledHeadline>> {
+    let mut headlines = Vec::new();
+
+    for headline in org.headlines() {
+        let title = headline.title(org);
+
+        if title.is_archived() || !belongs_to(title, conf, calendar) {
+            continue;
+        }
+
+        let repeater = |property: &str| {
+            title
+                .properties
+                .get(property)
+                .and_then(|cookies| Cookies::parse(cookies).repeater)
+        };
+
+        // repeating timestamps recur into the window until stopped
+        let in_window = |range: Option<TimeRange>, repeater: Option<Repeater>| {
+            range.filter(|range| match repeater {
+                Some(_) => to_utc(&range.start, tz) <= time_max,
+                None => range.overlaps(time_min, time_max, tz),
+            })
+        };
+
+        let scheduled_repeater = repeater(SCHEDULED_PROPERTY);
+        let deadline_repeater = repeater(DEADLINE_PROPERTY);
+
+        let scheduled = in_window(
+            title.scheduled().and_then(TimeRange::from_timestamp),
+            scheduled_repeater,
+        );
+        let deadline = in_window(
+            title.deadline().and_then(TimeRange::from_timestamp),
+            deadline_repeater,
+        );
+        let timestamp = in_window(active_timestamp(org, headline), None);
+
+        let repeater = if scheduled.is_some() {
+            scheduled_repeater
+        } else if deadline.is_some() {
+            deadline_repeater
+        } else if timestamp.is_some() {
+            None
+        } else {
+            continue;
+        };
+
+        let warning = if scheduled.is_none() && deadline.is_some() {
+            title
+                .properties
+                .get(DEADLINE_PROPERTY)
+                .and_then(|cookies| Cookies::parse(cookies).delay)
+                .and_then(|delay| warning_minutes(&delay))
+        } else {
+            None
+        };
+
+        headlines.push(ScheduledHeadline {
+            headline,
+            title: title.raw.to_string(),
+            scheduled,
+            deadline,
+            timestamp,
+            repeater,
+            body: section_text(org, headline, false)?,
+            location: conf
+                .fields
+                .location
+                .as_ref()
+                .and_then(|property| title.properties.get(property.as_str()))
+                .map(|location| location.to_string()),
+            tags: title.tags.iter().map(|tag| tag.to_string()).collect(),
+            priority: title.priority,
+            reminders: conf
+                .fields
+                .reminders
+                .as_ref()
+                .and_then(|property| title.properties.get(property.as_str()))
+                .map(|reminders| parse_reminders(reminders))
+                .unwrap_or_default(),
+            warning,
+            attendees: conf
+                .fields
+                .attendees
+                .as_ref()
+                .and_then(|property| title.properties.get(property.as_str()))
+                .map(|attendees| parse_attendees(attendees))
+                .unwrap_or_default(),
+            event_id: title
+                .properties
+                .get(conf.property.as_str())
+                .map(|id| id.to_string()),
+            tz,
+        });
+    }
+
+    Ok(headlines)
+}
+
+/// Returns `true` if the headline belongs to `calendar`
+///
+/// A headline belongs to the calendar in its calendar property. Linked
+/// headlines without one belong to the first calendar, and other headlines
+/// to the first calendar with one of their tags, or else without tag.
+fn belongs_to(title: &Title, conf: &GoogleCalendarConf, calendar: &CalendarConf) -> bool {
+    if let Some(id) = title.properties.get(conf.calendar_property.as_str()) {
+        return *id == calendar.calendar;
+    }
+
+    let calendars = conf.calendars();
+    let tagged = |tag: &String| title.tags.iter().any(|t| t == tag);
+
+    let owner = if title.properties.contains_key(conf.property.as_str()) {
+        calendars.first()
+    } else {
+        calendars
+            .iter()
+            .find(|c| c.tag.as_ref().is_some_and(tagged))
+            .or_else(|| calendars.iter().find(|c| c.tag.is_none()))
+    };
+
+    owner.is_some_and(|owner| owner.calendar == calendar.calendar)
+}
+
+fn parse_config(conf: &GoogleCalendarConf) -> ParseConfig {
+    let mut config = ParseConfig::default();
+
+    if let DeletePolicy::Keyword(keyword) = &conf.on_delete {
+        if !config.todo_keywords.0.contains(keyword) && !config.todo_keywords.1.contains(keyword) {
+            config.todo_keywords.1.push(keyword.clone());
+        }
+    }
+
+    config
+}
+
+/// Returns `true` if this headline was already marked as deleted
+fn is_marked_deleted(org: &Org, headline: Headline, conf: &GoogleCalendarConf) -> bool {
+    let title = headline.title(org);
+
+    match &conf.on_delete {
+        DeletePolicy::Remove => false,
+        DeletePolicy::Keyword(keyword) => title.keyword.as_deref() == Some(keyword),
+        DeletePolicy::Tag(tag) => title.tags.iter().any(|t| t == tag),
+    }
+}
+
+/// Removes or marks a headline whose event was deleted
+fn mark_deleted(org: &mut Org, headline: Headline, conf: &GoogleCalendarConf) {
+    match &conf.on_delete {
+        DeletePolicy::Remove => headline.detach(org),
+        DeletePolicy::Keyword(keyword) => {
+            headline.title_mut(org).keyword = Some(keyword.clone().into());
+        }
+        DeletePolicy::Tag(tag) => headline.title_mut(org).tags.push(tag.clone().into()),
+    }
+}
+
+/// Returns the headline titled `title`, creating it at the end of the document if missing
+fn find_or_create_headline(org: &mut Org, title: &str) -> Result<Headline> {
+    if let Some(headline) = org.headlines().find(|h| h.title(org).raw == title) {
+        return Ok(headline);
+    }
+
+    debug!("Creating headline \"{}\".", title);
+
+    let headline = Headline::new(
+        Title {
+            level: 1,
+            raw: title.to_string().into(),
+            ..Default::default()
+        },
+        org,
+    );
+
+    org.document().append(headline, org)?;
+
+    Ok(headline)
+}
+
+/// Rewrites the title, timestamp and mapped fields of a linked headline from its event
+fn update_headline(
+    org: &mut Org,
+    headline: &ScheduledHeadline,
+    event: &Event,
+    conf: &GoogleCalendarConf,
+) -> Result<()> {
+    if headline.title != event.summary {
+        headline
+            .headline
+            .set_title_content(event.summary.clone(), org);
+    }
+
+    let fields = read_fields(event, &conf.fields);
+
+    if let Some(body) = fields.body.filter(|body| *body != headline.body) {
+        // drawers are not synchronized, so keep them
+        let drawers = section_text(org, headline.headline, true)?;
+        let mut section = headline.headline;
+        section.set_section_content(format!("{}{}", drawers, with_newline(body)), org);
+    }
+
+    if let Some(property) = &conf.fields.location {
+        if fields.location != headline.location {
+            let properties = &mut headline.headline.title_mut(org).properties;
+
+            match fields.location {
+                Some(location) => {
+                    properties.insert(property.clone().into(), location.into());
+                }
+                None => {
+                    properties.remove(property.as_str());
+                }
+            }
+        }
+    }
+
+    let (reminders, warning) = read_reminders(event, headline.warning);
+
+    if let Some(property) = &conf.fields.reminders {
+        if reminders != headline.reminders {
+            let properties = &mut headline.headline.title_mut(org).properties;
+
+            if reminders.is_empty() {
+                properties.remove(property.as_str());
+            } else {
+                properties.insert(property.clone().into(), format_reminders(&reminders).into());
+            }
+        }
+    }
+
+    if let Some(property) = &conf.fields.attendees {
+        let attendees = format_attendees(event.attendees.as_deref().unwrap_or_default());
+
+        if attendees != format_attendees(&headline.attendees) {
+            let properties = &mut headline.headline.title_mut(org).properties;
+
+            if attendees.is_empty() {
+                properties.remove(property.as_str());
+            } else {
+                properties.insert(property.clone().into(), attendees.into());
+            }
+        }
+    }
+
+    // removing its reminder removes the warning period of the deadline
+    if headline.warning.is_some() && !warning {
+        update_cookies(org, headline.headline, DEADLINE_PROPERTY, |cookies| {
+            cookies.delay = None
+        });
+    }
+
+    let mut tags = fields.tags.unwrap_or_else(|| headline.tags.clone());
+    let mut priority = headline.priority;
+
+    if let Some(color) = &event.color_id {
+        apply_color_id(&conf.colors, color, &mut tags, &mut priority);
+    }
+
+    if tags != headline.tags {
+        headline.headline.title_mut(org).tags = tags.into_iter().map(Into::into).collect();
+    }
+
+    if priority != headline.priority {
+        headline.headline.title_mut(org).priority = priority;
+    }
+
+    if let Some(timestamp) = event_timestamp(event, headline.tz)? {
+        let is_deadline = headline.scheduled.is_none() && headline.deadline.is_some();
+
+        // keeps the timestamp as the user wrote it unless the time changed
+        let local = headline.to_event(conf);
+        if !same_time(&local.start, &event.start) || !same_time(&local.end, &event.end) {
+            let planning = headline
+                .headline
+                .title_mut(org)
+                .planning
+                .get_or_insert_with(|| {
+                    Box::new(Planning {
+                        deadline: None,
+                        scheduled: None,
+                        closed: None,
+                    })
+                });
+
+            if is_deadline {
+                planning.deadline = Some(timestamp);
+            } else {
+                planning.scheduled = Some(timestamp);
+            }
+        }
+
+        let property = if is_deadline {
+            DEADLINE_PROPERTY
+        } else {
+            SCHEDULED_PROPERTY
+        };
+
+        set_repeater(
+            org,
+            headline.headline,
+            property,
+            event_repeater(event, headline.tz),
+        );
+    }
+
+    Ok(())
+}
+
+/// Replaces the repeater stored in `property`, keeping its kind and the delay
+fn set_repeater(org: &mut Org, headline: Headline, property: &str, repeater: Option<Repeater>) {
+    update_cookies(org, headline, property, |cookies| {
+        cookies.repeater = match (cookies.repeater, repeater) {
+            (Some(old), Some(new)) => Some(Repeater {
+                kind: old.kind,
+                ..new
+            }),
+            (_, new) => new,
+        };
+    });
+}
+
+/// Updates the planning cookies stored in `property`
+fn update_cookies(org: &mut Org, headline: Headline, property: &str, f: impl FnOnce(&mut Cookies)) {
+    let properties = &mut headline.title_mut(org).properties;
+
+    let mut cookies = properties
+        .get(property)
+        .map(|cookies| Cookies::parse(cookies))
+        .unwrap_or_default();
+
+    f(&mut cookies);
+
+    if cookies.is_empty() {
+        properties.remove(property);
+    } else {
+        properties.insert(property.to_string().into(), cookies.to_string().into());
+    }
+}
+
+/// Appends a calendar event as a child headline of `parent`
+fn append_event(
+    org: &mut Org,
+    parent: Headline,
+    event: &Event,
+    conf: &GoogleCalendarConf,
+    calendar: &CalendarConf,
+    tz: Tz,
+) -> Result<()> {
+    let scheduled = event_timestamp(event, tz)?;
+
+    let mut properties = HashMap::new();
+    if let Some(id) = &event.id {
+        properties.insert(conf.property.clone().into(), id.clone().into());
+        properties.insert(
+            conf.calendar_property.clone().into(),
+            calendar.calendar.clone().into(),
+        );
+    }
+    if let Some(repeater) = event_repeater(event, tz) {
+        properties.insert(SCHEDULED_PROPERTY.into(), repeater.to_string().into());
+    }
+
+    let fields = read_fields(event, &conf.fields);
+
+    if let (Some(property), Some(location)) = (&conf.fields.location, fields.location) {
+        properties.insert(property.clone().into(), location.into());
+    }
+
+    let (reminders, _) = read_reminders(event, None);
+
+    if let (Some(property), false) = (&conf.fields.reminders, reminders.is_empty()) {
+        properties.insert(property.clone().into(), format_reminders(&reminders).into());
+    }
+
+    if let (Some(property), Some(attendees)) = (&conf.fields.attendees, &event.attendees) {
+        if !attendees.is_empty() {
+            properties.insert(property.clone().into(), format_attendees(attendees).into());
+        }
+    }
+
+    let mut tags = fields.tags.unwrap_or_default();
+    let mut priority = None;
+
+    if let Some(color) = &event.color_id {
+        apply_color_id(&conf.colors, color, &mut tags, &mut priority);
+    }
+
+    if let Some(tag) = calendar.tag.as_ref().filter(|tag| !tags.contains(tag)) {
+        tags.push(tag.clone());
+    }
+
+    let mut headline = Headline::new(
+        Title {
+            level: parent.level() + 1,
+            raw: event.summary.clone().into(),
+            planning: scheduled.map(|scheduled| {
+                Box::new(Planning {
+                    scheduled: Some(scheduled),
+                    deadline: None,
+                    closed: None,
+                })
+            }),
+            properties,
+            tags: tags.into_iter().map(Into::into).collect(),
+            priority,
+            ..Default::default()
+        },
+        org,
+    );
+
+    if let Some(body) = fields.body.filter(|body| !body.is_empty()) {
+        headline.set_section_content(with_newline(body), org);
+    }
+
+    parent.append(headline, org)?;
+
+    Ok(())
+}
+
+/// Returns the first active timestamp in the title or section of this headline
+fn active_timestamp(org: &Org, headline: Headline) -> Option<TimeRange> {
+    let title = headline.title_node().descendants(org.arena());
+    let section = headline
+        .section_node()
+        .into_iter()
+        .flat_map(|node| node.descendants(org.arena()));
+
+    title
+        .chain(section)
+        .filter_map(|node| match &org[node] {
+            Element::Timestamp(timestamp @ Timestamp::Active { .. })
+            | Element::Timestamp(timestamp @ Timestamp::ActiveRange { .. }) => {
+                TimeRange::from_timestamp(timestamp)
+            }
+            _ => None,
+        })
+        .next()
+}
+
+/// Renders the section of this headline as org text
+///
+/// Renders only the drawers if `drawers` is `true`, or everything except
+/// the drawers otherwise.
+fn section_text(org: &Org, headline: Headline, drawers: bool) -> Result<String> {
+    let mut handler = DefaultOrgHandler;
+    let mut text = Vec::new();
+    let mut depth = 0;
+
+    if let Some(section) = headline.section_node() {
+        for edge in section.traverse(org.arena()) {
+            match edge {
+                NodeEdge::Start(node) => {
+                    if let Element::Drawer(_) = org[node] {
+                        depth += 1;
+                    }
+                    if (depth > 0) == drawers {
+                        handler.start(&mut text, &org[node])?;
+                    }
+                }
+                NodeEdge::End(node) => {
+                    if (depth > 0) == drawers {
+                        handler.end(&mut text, &org[node])?;
+                    }
+                    if let Element::Drawer(_) = org[node] {
+                        depth -= 1;
+                    }
+                }
+            }
+        }
+    }
+
+    let text = String::from_utf8_lossy(&text);
+
+    Ok(if drawers {
+        text.into_owned()
+    } else {
+        text.trim().to_string()
+    })
+}
+
+fn with_newline(text: String) -> String {
+    if text.is_empty() || text.ends_with('\n') {
+        text
+    } else {
+        text + "\n"
+    }
 }
@@ -1,54 +1,197 @@
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, NaiveDate, Utc};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
-#[derive(Default, Serialize, Deserialize)]
+#[derive(Clone, Default, Serialize, Deserialize)]
 #[serde(default)]
 pub struct Event {
     /// Type of the resource ("calendar#event").
+    #[serde(skip_serializing_if = "String::is_empty")]
     pub kind: String,
+    /// ETag of the resource, changed whenever the event is. Read-only.
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub etag: String,
     /// Opaque identifier of the event.
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub id: Option<String>,
+    /// Status of the event ("confirmed", "tentative" or "cancelled").
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<String>,
     /// Whether this is a locked. The default is False. Read-Only.
     pub locked: bool,
     /// Whether the end time is actually unspecified.
     #[serde(rename = "endTimeUnspecified")]
     pub end_time_unspecified: bool,
     /// The id of the recurring event to which this instance belongs. Immutable.
-    #[serde(rename = "recurringEventId")]
+    #[serde(rename = "recurringEventId", skip_serializing_if = "Option::is_none")]
     pub recurring_event_id: Option<String>,
+    /// List of RRULE, EXRULE, RDATE and EXDATE lines for a recurring event.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub recurrence: Vec<String>,
 
     /// The (inclusive) start time of the event.
     pub start: EventDateTime,
     /// The (exclusive) end time of the event.
     pub end: EventDateTime,
     /// Last modification time of the event (as a RFC3339 timestamp). Read-only.
+    #[serde(skip_serializing_if = "String::is_empty")]
     pub updated: String,
     /// Creation time of the event (as a RFC3339 timestamp). Read-only.
+    #[serde(skip_serializing_if = "String::is_empty")]
     pub created: String,
 
     /// Title of the event.
     pub summary: String,
     /// Description of the event. Optional.
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub description: Option<String>,
     /// Geographic location of this event. Optional.
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub location: Option<String>,
     /// The color of the event. Optional.
-    #[serde(rename = "colorId")]
+    #[serde(rename = "colorId", skip_serializing_if = "Option::is_none")]
     pub color_id: Option<String>,
+    /// Extended properties of the event.
+    #[serde(rename = "extendedProperties", skip_serializing_if = "Option::is_none")]
+    pub extended_properties: Option<ExtendedProperties>,
+    /// Information about the event's reminders for the authenticated user.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reminders: Option<Reminders>,
+    /// The attendees of the event.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub attendees: Option<Vec<Attendee>>,
 }
 
-#[derive(Default, Serialize, Deserialize)]
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct Attendee {
+    /// The attendee's email address.
+    pub email: String,
+    /// The attendee's response status ("needsAction", "declined",
+    /// "tentative" or "accepted").
+    #[serde(rename = "responseStatus", skip_serializing_if = "Option::is_none")]
+    pub response_status: Option<String>,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Reminders {
+    /// Whether the default reminders of the calendar apply to the event.
+    #[serde(rename = "useDefault")]
+    pub use_default: bool,
+    /// If the event doesn't use the default reminders, this lists the
+    /// reminders specific to the event.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub overrides: Vec<EventReminder>,
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct EventReminder {
+    /// The method used by this reminder ("email" or "popup").
+    pub method: String,
+    /// Number of minutes before the start of the event when the reminder
+    /// should trigger.
+    pub minutes: u32,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ExtendedProperties {
+    /// Properties that are private to the copy of the event that appears on this calendar.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub private: BTreeMap<String, String>,
+    /// Properties that are shared between copies of the event on other attendees' calendars.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub shared: BTreeMap<String, String>,
+}
+
+impl Event {
+    /// Returns `true` if this event was deleted.
+    pub fn is_cancelled(&self) -> bool {
+        self.status.as_deref() == Some("cancelled")
+    }
+}
+
+/// Error response of the Google APIs, or of the OAuth endpoints
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum ErrorResponse {
+    Api {
+        error: ErrorDetails,
+    },
+    OAuth {
+        /// Error code, e.g. "invalid_grant".
+        error: String,
+        #[serde(default)]
+        error_description: String,
+    },
+}
+
+#[derive(Deserialize)]
+pub struct ErrorDetails {
+    #[serde(default)]
+    pub errors: Vec<ErrorItem>,
+    /// Status of the error, e.g. "PERMISSION_DENIED".
+    pub status: Option<String>,
+    #[serde(default)]
+    pub message: String,
+}
+
+#[derive(Deserialize)]
+pub struct ErrorItem {
+    /// Reason of the error, e.g. "rateLimitExceeded".
+    pub reason: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CalendarListEntry {
+    /// Identifier of the calendar.
+    pub id: String,
+    /// Title of the calendar.
+    pub summary: String,
+    /// The summary that the authenticated user has set for this calendar.
+    pub summary_override: Option<String>,
+    /// The effective access role that the authenticated user has on the
+    /// calendar ("freeBusyReader", "reader", "writer" or "owner").
+    pub access_role: String,
+    /// The time zone of the calendar.
+    pub time_zone: Option<String>,
+}
+
+impl CalendarListEntry {
+    /// Returns the title of the calendar as shown to the user
+    pub fn name(&self) -> &str {
+        self.summary_override.as_deref().unwrap_or(&self.summary)
+    }
+}
+
+#[derive(Deserialize)]
+pub struct Colors {
+    /// Palette of event colors, keyed by color id.
+    pub event: BTreeMap<String, ColorDefinition>,
+}
+
+#[derive(Deserialize)]
+pub struct ColorDefinition {
+    /// The background color associated with this color definition.
+    pub background: String,
+    /// The foreground color that can be used to write on top of a background.
+    pub foreground: String,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
 pub struct EventDateTime {
     /// The date, in the format "yyyy-mm-dd", if this is an all-day event.
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub date: Option<String>,
     /// The time zone in which the time is specified.
     /// (Formatted as an IANA Time Zone Database name, e.g. "Europe/Zurich".)
-    #[serde(rename = "timeZone")]
+    #[serde(rename = "timeZone", skip_serializing_if = "Option::is_none")]
     pub time_zone: Option<String>,
     /// The time, as a combined date-time value (formatted according to
     /// RFC3339). A time zone offset is required unless a time zone is
     /// explicitly specified in timeZone.
-    #[serde(rename = "dateTime")]
+    #[serde(rename = "dateTime", skip_serializing_if = "Option::is_none")]
     pub date_time: Option<String>,
 }
 
@@ -60,3 +203,12 @@ impl From<DateTime<Utc>> for EventDateTime {
         }
     }
 }
+
+impl From<NaiveDate> for EventDateTime {
+    fn from(date: NaiveDate) -> Self {
+        EventDateTime {
+            date: Some(date.format("%Y-%m-%d").to_string()),
+            ..Default::default()
+        }
+    }
+}
@@ -0,0 +1,550 @@
+//! Org timestamp repeaters and their mapping to recurrence rules
+//!
+//! orgize doesn't parse repeater and delay cookies, and a planning line
+//! containing one is not recognized at all. So before parsing, the cookies
+//! of `SCHEDULED` and `DEADLINE` are moved into the properties drawer of
+//! the headline (see [`extract`]), and moved back after writing (see
+//! [`restore`]). Time ranges within a day are written back in their
+//! compact form as well.
+
+use chrono::Weekday;
+use std::fmt;
+
+/// Property holding the cookies of the `SCHEDULED` timestamp
+pub const SCHEDULED_PROPERTY: &str = "SCHEDULED_REPEATER";
+/// Property holding the cookies of the `DEADLINE` timestamp
+pub const DEADLINE_PROPERTY: &str = "DEADLINE_REPEATER";
+/// Property marking an empty properties drawer of the user, which orgize
+/// would drop otherwise
+const DRAWER_PROPERTY: &str = "ORGIZE_SYNC_DRAWER";
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RepeaterKind {
+    /// `+`, shifts the date by the interval once
+    Cumulate,
+    /// `++`, shifts the date by the interval until it's in the future
+    CatchUp,
+    /// `.+`, shifts the date by the interval from today
+    Restart,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Repeater {
+    pub kind: RepeaterKind,
+    pub value: u32,
+    /// One of `h`, `d`, `w`, `m` and `y`
+    pub unit: char,
+}
+
+impl Repeater {
+    fn parse(cookie: &str) -> Option<Repeater> {
+        let (kind, rest) = if let Some(rest) = cookie.strip_prefix(".+") {
+            (RepeaterKind::Restart, rest)
+        } else if let Some(rest) = cookie.strip_prefix("++") {
+            (RepeaterKind::CatchUp, rest)
+        } else {
+            (RepeaterKind::Cumulate, cookie.strip_prefix('+')?)
+        };
+
+        let unit = rest.chars().last()?;
+        if !"hdwmy".contains(unit) {
+            return None;
+        }
+
+        Some(Repeater {
+            kind,
+            value: rest[..rest.len() - 1].parse().ok()?,
+            unit,
+        })
+    }
+
+    /// Converts this repeater into a recurrence rule
+    ///
+    /// Calendars can only repeat from the original date, so all kinds of
+    /// repeaters map to the same rule.
+    pub fn to_rrule(self) -> String {
+        let freq = match self.unit {
+            'h' => "HOURLY",
+            'd' => "DAILY",
+            'w' => "WEEKLY",
+            'm' => "MONTHLY",
+            _ => "YEARLY",
+        };
+
+        format!("RRULE:FREQ={};INTERVAL={}", freq, self.value)
+    }
+
+    /// Converts the recurrence of an event starting on `weekday` into a repeater
+    ///
+    /// Returns `None` if the recurrence can't be expressed as a repeater,
+    /// e.g. it ends after a count or has exceptions.
+    pub fn from_recurrence(recurrence: &[String], weekday: Weekday) -> Option<Repeater> {
+        let rule = match recurrence {
+            [rule] => rule.strip_prefix("RRULE:")?,
+            _ => return None,
+        };
+
+        let mut unit = None;
+        let mut value = 1;
+        let mut by_weekday = false;
+
+        for part in rule.split(';') {
+            let (key, val) = part.split_once('=')?;
+
+            match key {
+                "FREQ" => {
+                    unit = Some(match val {
+                        "HOURLY" => 'h',
+                        "DAILY" => 'd',
+                        "WEEKLY" => 'w',
+                        "MONTHLY" => 'm',
+                        "YEARLY" => 'y',
+                        _ => return None,
+                    })
+                }
+                "INTERVAL" => value = val.parse().ok()?,
+                "WKST" => (),
+                // weekly events created in Google Calendar repeat on the weekday they start
+                "BYDAY" if val == weekday_code(weekday) => by_weekday = true,
+                _ => return None,
+            }
+        }
+
+        if by_weekday && unit != Some('w') {
+            return None;
+        }
+
+        Some(Repeater {
+            kind: RepeaterKind::Cumulate,
+            value,
+            unit: unit?,
+        })
+    }
+}
+
+impl fmt::Display for Repeater {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = match self.kind {
+            RepeaterKind::Cumulate => "+",
+            RepeaterKind::CatchUp => "++",
+            RepeaterKind::Restart => ".+",
+        };
+
+        write!(f, "{}{}{}", kind, self.value, self.unit)
+    }
+}
+
+fn weekday_code(weekday: Weekday) -> &'static str {
+    match weekday {
+        Weekday::Mon => "MO",
+        Weekday::Tue => "TU",
+        Weekday::Wed => "WE",
+        Weekday::Thu => "TH",
+        Weekday::Fri => "FR",
+        Weekday::Sat => "SA",
+        Weekday::Sun => "SU",
+    }
+}
+
+/// Repeater and delay cookies of a timestamp, e.g. `+1w -2d`
+#[derive(Default)]
+pub struct Cookies {
+    pub repeater: Option<Repeater>,
+    /// Delay cookie, kept as is
+    pub delay: Option<String>,
+}
+
+impl Cookies {
+    pub fn parse(text: &str) -> Cookies {
+        let mut cookies = Cookies::default();
+
+        for cookie in text.split_whitespace() {
+            if cookie.starts_with('-') {
+                cookies.delay = Some(cookie.to_string());
+            } else if let Some(repeater) = Repeater::parse(cookie) {
+                cookies.repeater = Some(repeater);
+            }
+        }
+
+        cookies
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.repeater.is_none() && self.delay.is_none()
+    }
+}
+
+impl fmt::Display for Cookies {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (&self.repeater, &self.delay) {
+            (Some(repeater), Some(delay)) => write!(f, "{} {}", repeater, delay),
+            (Some(repeater), None) => write!(f, "{}", repeater),
+            (None, Some(delay)) => write!(f, "{}", delay),
+            (None, None) => Ok(()),
+        }
+    }
+}
+
+fn is_headline(line: &str) -> bool {
+    let stars = line.len() - line.trim_start_matches('*').len();
+
+    stars > 0 && line[stars..].starts_with(' ')
+}
+
+fn is_planning(line: &str) -> bool {
+    let line = line.trim_start();
+
+    line.starts_with("SCHEDULED:") || line.starts_with("DEADLINE:") || line.starts_with("CLOSED:")
+}
+
+/// Returns the byte range of the cookies in the timestamp following `keyword`
+fn cookies_range(line: &str, keyword: &str) -> Option<(usize, usize)> {
+    let start = line.find(keyword)? + keyword.len();
+    let open = start + line[start..].find(['<', '['])?;
+    let close = open + line[open..].find(['>', ']'])?;
+
+    let inner = &line[open + 1..close];
+    let date_end = inner.find(' ')?;
+    let offset = inner[date_end..]
+        .match_indices(' ')
+        .map(|(i, _)| date_end + i)
+        .find(|i| {
+            let cookie = &inner[i + 1..];
+            cookie.starts_with('+') || cookie.starts_with(".+") || cookie.starts_with('-')
+        })?;
+
+    Some((open + 1 + offset, close))
+}
+
+/// Moves the cookies of planning timestamps into properties
+///
+/// Empty properties drawers are marked, so that they are kept as well.
+pub fn extract(text: &str) -> String {
+    let mut output = String::with_capacity(text.len());
+    let mut lines = text.split_inclusive('\n').peekable();
+
+    while let Some(line) = lines.next() {
+        output.push_str(line);
+
+        if !is_headline(line) {
+            continue;
+        }
+
+        let mut properties = Vec::new();
+
+        if lines.peek().is_some_and(|line| is_planning(line)) {
+            let mut planning = lines.next().unwrap().to_string();
+
+            for (keyword, property) in &[
+                ("SCHEDULED:", SCHEDULED_PROPERTY),
+                ("DEADLINE:", DEADLINE_PROPERTY),
+            ] {
+                if let Some((start, end)) = cookies_range(&planning, keyword) {
+                    properties.push(format!(":{}: {}\n", property, planning[start..end].trim()));
+                    planning.replace_range(start..end, "");
+                }
+            }
+
+            if !planning.ends_with('\n') && !properties.is_empty() {
+                planning.push('\n');
+            }
+            output.push_str(&planning);
+        }
+
+        if lines
+            .peek()
+            .is_some_and(|line| line.trim() == ":PROPERTIES:")
+        {
+            output.push_str(lines.next().unwrap());
+
+            if lines.peek().is_some_and(|line| line.trim() == ":END:") {
+                properties.push(format!(":{}: t\n", DRAWER_PROPERTY));
+            }
+
+            properties.into_iter().for_each(|p| output.push_str(&p));
+        } else if !properties.is_empty() {
+            output.push_str(":PROPERTIES:\n");
+            properties.into_iter().for_each(|p| output.push_str(&p));
+            output.push_str(":END:\n");
+        }
+    }
+
+    output
+}
+
+/// Writes same-day time ranges like `<2026-10-01 Thu 10:00-11:30>`
+///
+/// orgize writes them as two timestamps, which agendas don't support in
+/// planning lines.
+fn compact_ranges(planning: &str) -> String {
+    let mut output = String::with_capacity(planning.len());
+    let mut rest = planning;
+
+    while let Some(open) = rest.find('<') {
+        output.push_str(&rest[..open]);
+        rest = &rest[open..];
+
+        // <2026-10-01 Thu 10:00>--<2026-10-01 Thu 11:30>
+        let range = (|| {
+            let close = rest.find('>')?;
+            let start = &rest[1..close];
+            let end = rest[close + 1..].strip_prefix("--<")?;
+            let end = &end[..end.find('>')?];
+
+            let (start_date, start_time) = start.rsplit_once(' ')?;
+            let (end_date, end_time) = end.rsplit_once(' ')?;
+
+            if start_date != end_date || !start_time.contains(':') || !end_time.contains(':') {
+                return None;
+            }
+
+            Some((
+                format!("<{}-{}>", start, end_time),
+                close + "--<".len() + end.len() + 2,
+            ))
+        })();
+
+        match range {
+            Some((range, len)) => {
+                output.push_str(&range);
+                rest = &rest[len..];
+            }
+            None => {
+                output.push('<');
+                rest = &rest[1..];
+            }
+        }
+    }
+
+    output.push_str(rest);
+    output
+}
+
+/// Moves the cookies stored in properties back to planning timestamps
+pub fn restore(text: &str) -> String {
+    let lines: Vec<&str> = text.split_inclusive('\n').collect();
+    let mut output = String::with_capacity(text.len());
+    let mut i = 0;
+
+    while i < lines.len() {
+        output.push_str(lines[i]);
+        i += 1;
+
+        if !is_headline(lines[i - 1]) {
+            continue;
+        }
+
+        let planning = lines.get(i).filter(|line| is_planning(line)).map(|line| {
+            i += 1;
+            line.to_string()
+        });
+
+        let mut drawer = Vec::new();
+        let mut cookies = Vec::new();
+        let mut keep_drawer = false;
+
+        if lines
+            .get(i)
+            .is_some_and(|line| line.trim() == ":PROPERTIES:")
+        {
+            while let Some(line) = lines.get(i) {
+                i += 1;
+
+                let property = [SCHEDULED_PROPERTY, DEADLINE_PROPERTY, DRAWER_PROPERTY]
+                    .iter()
+                    .find(|p| line.trim_start().starts_with(&format!(":{}:", p)));
+
+                match property {
+                    Some(&DRAWER_PROPERTY) => keep_drawer = true,
+                    Some(property) => {
+                        let value = line.trim().splitn(3, ':').nth(2).unwrap_or_default();
+                        cookies.push((*property, value.trim().to_string()));
+                    }
+                    None => drawer.push(*line),
+                }
+
+                if line.trim() == ":END:" {
+                    break;
+                }
+            }
+        }
+
+        if let Some(planning) = planning {
+            let mut planning = compact_ranges(&planning);
+
+            for (property, keyword) in &[
+                (SCHEDULED_PROPERTY, "SCHEDULED:"),
+                (DEADLINE_PROPERTY, "DEADLINE:"),
+            ] {
+                let cookie = cookies.iter().find(|(p, _)| p == property);
+
+                if let (Some((_, cookie)), Some(start)) = (cookie, planning.find(keyword)) {
+                    if let Some(close) = planning[start..].find(['>', ']']) {
+                        planning.insert_str(start + close, &format!(" {}", cookie));
+                    }
+                }
+            }
+
+            output.push_str(&planning);
+        }
+
+        // drop the drawer if it only contained the cookies
+        if drawer.len() > 2 || keep_drawer || cookies.is_empty() {
+            drawer.into_iter().for_each(|line| output.push_str(line));
+        }
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use orgize::Org;
+
+    fn round_trip(text: &str) {
+        assert_eq!(restore(&extract(text)), text);
+    }
+
+    /// Parses and writes the text with orgize in between, as when syncing
+    fn round_trip_org(text: &str) {
+        let mut content = Vec::new();
+        Org::parse(&extract(text)).write_org(&mut content).unwrap();
+
+        assert_eq!(restore(&String::from_utf8(content).unwrap()), text);
+    }
+
+    #[test]
+    fn repeater_with_delay() {
+        let text = "* TODO Review\nSCHEDULED: <2026-10-01 Thu +1w -2d>\n";
+
+        assert_eq!(
+            extract(text),
+            "* TODO Review\nSCHEDULED: <2026-10-01 Thu>\n\
+             :PROPERTIES:\n:SCHEDULED_REPEATER: +1w -2d\n:END:\n"
+        );
+        round_trip(text);
+        round_trip_org(text);
+    }
+
+    #[test]
+    fn scheduled_and_deadline() {
+        round_trip("* A\nSCHEDULED: <2026-10-01 Thu .+1d> DEADLINE: <2026-10-03 Sat -1d>\n");
+        round_trip_org("* A\nSCHEDULED: <2026-10-01 Thu ++2m> DEADLINE: <2026-10-03 Sat>\n");
+    }
+
+    #[test]
+    fn timed_range() {
+        let text = "* Standup\nSCHEDULED: <2026-10-01 Thu 10:00-10:15 +1d>\n";
+
+        assert_eq!(
+            extract(text),
+            "* Standup\nSCHEDULED: <2026-10-01 Thu 10:00-10:15>\n\
+             :PROPERTIES:\n:SCHEDULED_REPEATER: +1d\n:END:\n"
+        );
+        round_trip(text);
+    }
+
+    #[test]
+    fn compact_range() {
+        let text = "* Standup\nSCHEDULED: <2026-10-01 Thu 10:00-10:15 +1d>\n";
+
+        round_trip_org(text);
+        round_trip_org("* A\nDEADLINE: <2026-10-01 Thu 23:00>--<2026-10-02 Fri 01:00>\n");
+        round_trip_org("* A\nSCHEDULED: <2026-10-01 Thu>--<2026-10-03 Sat>\n");
+        assert_eq!(
+            compact_ranges("SCHEDULED: <2026-10-01 Thu 10:00>--<2026-10-01 Thu 11:30>\n"),
+            "SCHEDULED: <2026-10-01 Thu 10:00-11:30>\n"
+        );
+    }
+
+    #[test]
+    fn existing_drawer() {
+        let text = "* A\nSCHEDULED: <2026-10-01 Thu +1y>\n:PROPERTIES:\n:ID: a\n:END:\nBody\n";
+
+        assert_eq!(
+            extract(text),
+            "* A\nSCHEDULED: <2026-10-01 Thu>\n\
+             :PROPERTIES:\n:SCHEDULED_REPEATER: +1y\n:ID: a\n:END:\nBody\n"
+        );
+        round_trip(text);
+        round_trip_org(text);
+    }
+
+    #[test]
+    fn empty_drawer() {
+        round_trip("* A\nSCHEDULED: <2026-10-01 Thu +1w>\n:PROPERTIES:\n:END:\n");
+        round_trip("* A\n:PROPERTIES:\n:END:\n* B\n");
+        round_trip_org("* A\nSCHEDULED: <2026-10-01 Thu +1w>\n:PROPERTIES:\n:END:\n");
+        round_trip_org("* A\n:PROPERTIES:\n:END:\n* B\n");
+    }
+
+    #[test]
+    fn without_cookies() {
+        let text = "* A\nSCHEDULED: <2026-10-01 Thu>\nBody\n** B\n:PROPERTIES:\n:ID: b\n:END:\n";
+
+        assert_eq!(extract(text), text);
+        round_trip(text);
+        round_trip_org(text);
+    }
+
+    #[test]
+    fn rrule() {
+        let repeater = Repeater {
+            kind: RepeaterKind::CatchUp,
+            value: 2,
+            unit: 'w',
+        };
+
+        assert_eq!(repeater.to_rrule(), "RRULE:FREQ=WEEKLY;INTERVAL=2");
+        assert_eq!(
+            Repeater::from_recurrence(&[repeater.to_rrule()], Weekday::Mon),
+            Some(Repeater {
+                kind: RepeaterKind::Cumulate,
+                ..repeater
+            })
+        );
+    }
+
+    #[test]
+    fn from_recurrence() {
+        let parse = |rule: &str| Repeater::from_recurrence(&[rule.to_string()], Weekday::Thu);
+
+        assert_eq!(
+            parse("RRULE:FREQ=MONTHLY"),
+            Some(Repeater {
+                kind: RepeaterKind::Cumulate,
+                value: 1,
+                unit: 'm',
+            })
+        );
+        assert_eq!(
+            parse("RRULE:FREQ=WEEKLY;BYDAY=TH").map(|r| r.to_string()),
+            Some("+1w".into())
+        );
+        assert_eq!(parse("RRULE:FREQ=WEEKLY;BYDAY=MO"), None);
+        assert_eq!(parse("RRULE:FREQ=DAILY;BYDAY=TH"), None);
+        assert_eq!(parse("RRULE:FREQ=DAILY;COUNT=5"), None);
+        assert_eq!(parse("RRULE:FREQ=SECONDLY"), None);
+        assert_eq!(
+            Repeater::from_recurrence(
+                &[
+                    "RRULE:FREQ=DAILY".to_string(),
+                    "EXDATE:20261002T100000Z".to_string()
+                ],
+                Weekday::Thu
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn cookies() {
+        let cookies = Cookies::parse("+1w -2d");
+
+        assert_eq!(cookies.repeater.map(|r| r.to_string()), Some("+1w".into()));
+        assert_eq!(cookies.delay.as_deref(), Some("-2d"));
+        assert_eq!(cookies.to_string(), "+1w -2d");
+        assert!(Cookies::parse("").is_empty());
+    }
+}
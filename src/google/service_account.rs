@@ -0,0 +1,83 @@
+//! Service account keys and signed JWT assertions
+
+use chrono::{Duration, Utc};
+use ring::{
+    rand::SystemRandom,
+    signature::{RsaKeyPair, RSA_PKCS1_SHA256},
+};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+use crate::error::{Error, Result};
+
+/// Service account key, as downloaded from the Google Cloud console
+#[derive(Deserialize)]
+pub struct ServiceAccountKey {
+    pub client_email: String,
+    /// PKCS#8 private key in PEM format
+    pub private_key: String,
+    pub token_uri: String,
+}
+
+#[derive(Serialize)]
+struct Claims<'a> {
+    iss: &'a str,
+    scope: &'a str,
+    aud: &'a str,
+    iat: i64,
+    exp: i64,
+}
+
+impl ServiceAccountKey {
+    pub fn load(path: &Path) -> Result<Self> {
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    /// Creates a JWT assertion requesting `scope`, signed with RS256
+    pub fn assertion(&self, scope: &str) -> Result<String> {
+        let now = Utc::now();
+
+        let header = encode(br#"{"alg":"RS256","typ":"JWT"}"#);
+        let claims = encode(&serde_json::to_vec(&Claims {
+            iss: &self.client_email,
+            scope,
+            aud: &self.token_uri,
+            iat: now.timestamp(),
+            exp: (now + Duration::hours(1)).timestamp(),
+        })?);
+
+        let message = format!("{}.{}", header, claims);
+
+        let key_pair = RsaKeyPair::from_pkcs8(&self.private_key_der()?)
+            .map_err(|err| Error::OAuth(format!("invalid service account key: {}", err)))?;
+
+        let mut signature = vec![0; key_pair.public().modulus_len()];
+        key_pair
+            .sign(
+                &RSA_PKCS1_SHA256,
+                &SystemRandom::new(),
+                message.as_bytes(),
+                &mut signature,
+            )
+            .map_err(|_| Error::OAuth("failed to sign the JWT assertion".into()))?;
+
+        Ok(format!("{}.{}", message, encode(&signature)))
+    }
+
+    fn private_key_der(&self) -> Result<Vec<u8>> {
+        let base64: String = self
+            .private_key
+            .lines()
+            .filter(|line| !line.starts_with("-----"))
+            .map(str::trim)
+            .collect();
+
+        base64::decode(&base64)
+            .map_err(|err| Error::OAuth(format!("invalid service account key: {}", err)))
+    }
+}
+
+fn encode(input: &[u8]) -> String {
+    base64::encode_config(input, base64::URL_SAFE_NO_PAD)
+}
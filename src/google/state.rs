@@ -0,0 +1,68 @@
+use chrono::{DateTime, NaiveDate, Utc};
+use log::{debug, trace};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::{conf::GoogleCalendarGlobalConf, error::Result};
+
+/// Synchronization state, keyed by org file path and calendar id
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SyncState {
+    pub files: HashMap<String, HashMap<String, CalendarState>>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CalendarState {
+    /// Time of the last successful synchronization.
+    pub last_sync: Option<DateTime<Utc>>,
+    /// Events generated from linked headlines at the last synchronization,
+    /// keyed by event id.
+    pub headlines: HashMap<String, String>,
+    /// ETags of the linked events as last fetched, keyed by event id. Events
+    /// with another ETag were changed since, and writes to them are rejected.
+    pub etags: HashMap<String, String>,
+    /// Token for retrieving only the events changed since the last synchronization.
+    pub sync_token: Option<String>,
+    /// Date of the full listing the sync token originates from. The token
+    /// is only reused on the same day, so that events moving into the
+    /// window are still picked up.
+    pub sync_token_date: Option<NaiveDate>,
+}
+
+fn state_path(conf: &GoogleCalendarGlobalConf) -> PathBuf {
+    conf.token_dir.join(&conf.state_filename)
+}
+
+impl SyncState {
+    pub fn load(conf: &GoogleCalendarGlobalConf) -> Result<Self> {
+        let path = state_path(conf);
+
+        debug!("Google Calendar sync state path: {}", path.display());
+
+        if path.exists() {
+            Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+        } else {
+            Ok(SyncState::default())
+        }
+    }
+
+    pub fn save(&self, conf: &GoogleCalendarGlobalConf) -> Result<()> {
+        trace!("Saving Google Calendar sync state.");
+
+        fs::write(state_path(conf), serde_json::to_string(self)?)?;
+
+        Ok(())
+    }
+
+    pub fn calendar(&mut self, path: &str, calendar: &str) -> &mut CalendarState {
+        self.files
+            .entry(path.into())
+            .or_default()
+            .entry(calendar.into())
+            .or_default()
+    }
+}
@@ -0,0 +1,286 @@
+//! Storage of the OAuth token
+//!
+//! The token is stored in `token_dir`, readable only by the user, and
+//! optionally encrypted with a passphrase. A `token_command` can store it
+//! elsewhere instead, e.g. in a password manager.
+
+use chrono::{DateTime, Utc};
+use log::{debug, trace};
+use ring::{
+    aead::{Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305, NONCE_LEN},
+    pbkdf2,
+    rand::{SecureRandom, SystemRandom},
+};
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::num::NonZeroU32;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::sync::OnceLock;
+
+use crate::{
+    conf::{GoogleCalendarGlobalConf, TokenCommand},
+    error::{Error, Result},
+};
+
+/// Environment variable holding the passphrase of the token file
+const PASSPHRASE_VAR: &str = "ORGIZE_SYNC_TOKEN_PASSPHRASE";
+
+const PBKDF2_ITERATIONS: u32 = 100_000;
+const SALT_LEN: usize = 16;
+
+/// Passphrase entered at the prompt, asked only once per run
+static PASSPHRASE: OnceLock<String> = OnceLock::new();
+
+#[derive(Serialize, Deserialize)]
+pub struct OAuthToken {
+    pub access_token: String,
+    pub expires_at: DateTime<Utc>,
+    /// Empty for service accounts
+    pub refresh_token: String,
+}
+
+/// Token file encrypted with ChaCha20-Poly1305, with a key derived from the
+/// passphrase using PBKDF2
+#[derive(Serialize, Deserialize)]
+struct EncryptedToken {
+    salt: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+fn token_path(conf: &GoogleCalendarGlobalConf) -> PathBuf {
+    conf.token_dir.join(&conf.token_filename)
+}
+
+/// Loads the stored token, if any
+pub fn load(conf: &GoogleCalendarGlobalConf) -> Result<Option<OAuthToken>> {
+    let json = match &conf.token_command {
+        Some(command) => run_get(command)?,
+        None => {
+            let path = token_path(conf);
+
+            debug!("Google OAuth token path: {}", path.display());
+
+            match fs::read_to_string(&path) {
+                Ok(text) if conf.token_encryption => {
+                    // stored before encryption was turned on
+                    if let Ok(token) = serde_json::from_str::<OAuthToken>(&text) {
+                        debug!("Encrypting the plaintext Google OAuth token.");
+                        save(conf, &token)?;
+                        return Ok(Some(token));
+                    }
+
+                    Some(decrypt(&text)?)
+                }
+                Ok(text) => Some(text),
+                Err(_) => None,
+            }
+        }
+    };
+
+    match json {
+        Some(json) => Ok(Some(serde_json::from_str(&json)?)),
+        None => Ok(None),
+    }
+}
+
+pub fn save(conf: &GoogleCalendarGlobalConf, token: &OAuthToken) -> Result<()> {
+    trace!("Saving Google OAuth token.");
+
+    let json = serde_json::to_string(token)?;
+
+    if let Some(command) = &conf.token_command {
+        return run_store(command, &json);
+    }
+
+    let text = if conf.token_encryption {
+        encrypt(&json)?
+    } else {
+        json
+    };
+
+    let path = token_path(conf);
+
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+
+        options.mode(0o600);
+
+        // files created before are readable by others
+        if path.exists() {
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o600))?;
+        }
+    }
+
+    options.open(&path)?.write_all(text.as_bytes())?;
+
+    Ok(())
+}
+
+fn shell(command: &str) -> Command {
+    if cfg!(target_os = "windows") {
+        let mut cmd = Command::new("cmd");
+        cmd.args(["/C", command]);
+        cmd
+    } else {
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", command]);
+        cmd
+    }
+}
+
+/// Runs the get command, returning `None` if it prints nothing
+fn run_get(command: &TokenCommand) -> Result<Option<String>> {
+    debug!("Loading Google OAuth token with \"{}\".", command.get);
+
+    let output = shell(&command.get).stderr(Stdio::inherit()).output()?;
+
+    if !output.status.success() {
+        return Err(Error::Token(format!(
+            "token command exited with {}",
+            output.status
+        )));
+    }
+
+    let text = String::from_utf8_lossy(&output.stdout).trim().to_string();
+
+    Ok(Some(text).filter(|text| !text.is_empty()))
+}
+
+fn run_store(command: &TokenCommand, json: &str) -> Result<()> {
+    debug!("Storing Google OAuth token with \"{}\".", command.store);
+
+    let mut child = shell(&command.store).stdin(Stdio::piped()).spawn()?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(json.as_bytes())?;
+    }
+
+    let status = child.wait()?;
+
+    if status.success() {
+        Ok(())
+    } else {
+        Err(Error::Token(format!(
+            "token command exited with {}",
+            status
+        )))
+    }
+}
+
+fn passphrase() -> Result<String> {
+    if let Ok(passphrase) = env::var(PASSPHRASE_VAR) {
+        return Ok(passphrase);
+    }
+
+    if let Some(passphrase) = PASSPHRASE.get() {
+        return Ok(passphrase.clone());
+    }
+
+    let passphrase = rpassword::prompt_password("Google OAuth token passphrase: ")?;
+
+    Ok(PASSPHRASE.get_or_init(|| passphrase).clone())
+}
+
+fn derive_key(salt: &[u8]) -> Result<LessSafeKey> {
+    let mut key = [0; 32];
+
+    pbkdf2::derive(
+        pbkdf2::PBKDF2_HMAC_SHA256,
+        NonZeroU32::new(PBKDF2_ITERATIONS).unwrap(),
+        salt,
+        passphrase()?.as_bytes(),
+        &mut key,
+    );
+
+    Ok(LessSafeKey::new(
+        UnboundKey::new(&CHACHA20_POLY1305, &key).unwrap(),
+    ))
+}
+
+fn encrypt(json: &str) -> Result<String> {
+    let rng = SystemRandom::new();
+    let mut salt = [0; SALT_LEN];
+    let mut nonce = [0; NONCE_LEN];
+
+    rng.fill(&mut salt)
+        .and_then(|_| rng.fill(&mut nonce))
+        .map_err(|_| Error::Token("failed to generate random bytes".into()))?;
+
+    let mut ciphertext = json.as_bytes().to_vec();
+
+    derive_key(&salt)?
+        .seal_in_place_append_tag(
+            Nonce::assume_unique_for_key(nonce),
+            Aad::empty(),
+            &mut ciphertext,
+        )
+        .map_err(|_| Error::Token("failed to encrypt the token".into()))?;
+
+    Ok(serde_json::to_string(&EncryptedToken {
+        salt: base64::encode(&salt),
+        nonce: base64::encode(&nonce),
+        ciphertext: base64::encode(&ciphertext),
+    })?)
+}
+
+fn decrypt(text: &str) -> Result<String> {
+    let encrypted: EncryptedToken = serde_json::from_str(text)?;
+
+    let invalid = |_| Error::Token("invalid encrypted token file".into());
+    let salt = base64::decode(&encrypted.salt).map_err(invalid)?;
+    let nonce = base64::decode(&encrypted.nonce).map_err(invalid)?;
+    let mut ciphertext = base64::decode(&encrypted.ciphertext).map_err(invalid)?;
+
+    let nonce = Nonce::try_assume_unique_for_key(&nonce)
+        .map_err(|_| Error::Token("invalid encrypted token file".into()))?;
+
+    let json = derive_key(&salt)?
+        .open_in_place(nonce, Aad::empty(), &mut ciphertext)
+        .map_err(|_| Error::Token("failed to decrypt the token, wrong passphrase?".into()))?;
+
+    Ok(String::from_utf8_lossy(json).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    /// Tests set the passphrase variable of the whole process
+    static ENV: Mutex<()> = Mutex::new(());
+
+    #[test]
+    fn encryption_round_trip() {
+        let _env = ENV.lock().unwrap();
+        env::set_var(PASSPHRASE_VAR, "correct horse");
+
+        let json =
+            r#"{"access_token":"a","expires_at":"2026-10-01T09:00:00Z","refresh_token":"r"}"#;
+        let text = encrypt(json).unwrap();
+
+        assert!(!text.contains("access_token"));
+        assert_eq!(decrypt(&text).unwrap(), json);
+    }
+
+    #[test]
+    fn wrong_passphrase() {
+        let _env = ENV.lock().unwrap();
+        env::set_var(PASSPHRASE_VAR, "correct horse");
+        let text = encrypt("{}").unwrap();
+
+        env::set_var(PASSPHRASE_VAR, "battery staple");
+
+        match decrypt(&text) {
+            Err(Error::Token(message)) => assert!(message.contains("wrong passphrase")),
+            _ => panic!("expected a decryption failure"),
+        }
+    }
+}
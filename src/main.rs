@@ -61,6 +61,58 @@
 //!     -c, --conf-path <conf-path>    Path to configuration file
 //! ```
 //!
+//! ## `auth google`
+//!
+//! Authorizes access to Google Calendar
+//!
+//! ```text
+//! USAGE:
+//!     orgize-sync auth google [FLAGS] [OPTIONS]
+//!
+//! FLAGS:
+//!     -h, --help          Prints help information
+//!         --no-browser    Pastes the authorization code instead of receiving it on localhost
+//!     -V, --version       Prints version information
+//!     -v, --verbose       Increases verbosity
+//!
+//! OPTIONS:
+//!     -c, --conf-path <conf-path>    Path to configuration file
+//! ```
+//!
+//! ## `google calendars`
+//!
+//! Lists the calendars of the user
+//!
+//! ```text
+//! USAGE:
+//!     orgize-sync google calendars [FLAGS] [OPTIONS]
+//!
+//! FLAGS:
+//!     -h, --help       Prints help information
+//!     -V, --version    Prints version information
+//!     -v, --verbose    Increases verbosity
+//!
+//! OPTIONS:
+//!     -c, --conf-path <conf-path>    Path to configuration file
+//! ```
+//!
+//! ## `google colors`
+//!
+//! Lists the palette of event colors
+//!
+//! ```text
+//! USAGE:
+//!     orgize-sync google colors [FLAGS] [OPTIONS]
+//!
+//! FLAGS:
+//!     -h, --help       Prints help information
+//!     -V, --version    Prints version information
+//!     -v, --verbose    Increases verbosity
+//!
+//! OPTIONS:
+//!     -c, --conf-path <conf-path>    Path to configuration file
+//! ```
+//!
 //! # Configuration
 //!
 //! + [General](#general)
@@ -113,14 +165,46 @@
 //!         // Google OAuth client secret. Required.
 //!         // Sepcifying here or by setting the "GOOGLE_CLIENT_SECRET" environment variable.
 //!         "client_secret": "xxx",
-//!         // Redirect url after authorizing.
+//!         // Redirect url after authorizing with "--no-browser".
 //!         // The default is "http://localhost"
 //!         "redirect_uri": "",
+//!         // How to authorize when no token is stored: "browser", or "device"
+//!         // for machines without a browser.
+//!         // The default is "browser".
+//!         "auth_flow": "device",
+//!         // Path to the JSON key of a service account, used instead of authorizing
+//!         // as a user. "client_id" and "client_secret" are not needed then. The
+//!         // calendars must be shared with the email address of the service account.
+//!         // The default is null.
+//!         "service_account_key": "",
+//!         // Number of retries of requests which were rate limited or failed on the
+//!         // server, waiting longer after each one.
+//!         // The default is 5.
+//!         "max_retries": 5,
 //!         // Path to store the access token and refresh token.
 //!         // The default is "${UserCacheDir}/orgize-sync".
 //!         "token_dir": "",
 //!         // The default is "google-token.json".
-//!         "token_filename": ""
+//!         "token_filename": "",
+//!         // Whether to encrypt the token file with a passphrase, read from the
+//!         // "ORGIZE_SYNC_TOKEN_PASSPHRASE" environment variable or prompted for.
+//!         // A token file stored before is encrypted when it is next loaded.
+//!         // The default is false.
+//!         "token_encryption": true,
+//!         // Commands to load and store the token instead of the token file. "get"
+//!         // prints the token, or nothing if there is none, and "store" reads it
+//!         // from stdin.
+//!         // The default is null.
+//!         "token_command": {
+//!             "get": "pass show orgize-sync/google",
+//!             "store": "pass insert -m -f orgize-sync/google"
+//!         },
+//!         // Filename of the synchronization state, stored in "token_dir".
+//!         // The default is "google-state.json".
+//!         "state_filename": "",
+//!         // Time zone of org timestamps, as an IANA time zone name.
+//!         // The default is the system time zone.
+//!         "time_zone": "Europe/Zurich"
 //!     }
 //! }
 //! ```
@@ -132,8 +216,28 @@
 //!     "files": [
 //!         {
 //!             "google-calendar": {
-//!                 // Which calendar to sync. Required.
+//!                 // Which calendar to sync, by id or by name as listed by
+//!                 // "google calendars". Required, unless "calendars" is set.
 //!                 "calendar": "",
+//!                 // Other calendars to sync with this file.
+//!                 // The default is empty.
+//!                 "calendars": [
+//!                     {
+//!                         // Which calendar to sync, by id or by name. Required.
+//!                         "calendar": "",
+//!                         // Where to append its new events.
+//!                         // The default is "append_headline".
+//!                         "append_headline": "Work",
+//!                         // Headlines with this tag are synced with this calendar.
+//!                         // Headlines without a matching tag go to the first calendar
+//!                         // without tag. The default is null.
+//!                         "tag": "work",
+//!                         // Which side to update: "both", "push" for the calendar only
+//!                         // or "pull" for org only.
+//!                         // The default is "both".
+//!                         "direction": "both"
+//!                     }
+//!                 ],
 //!                 // Whether to append new calendar event to the org mode.
 //!                 // The default is true.
 //!                 "append_new": false,
@@ -143,12 +247,70 @@
 //!                 // Which property to store event id.
 //!                 // The default is "EVENT_ID".
 //!                 "property": "EVENT_ID",
+//!                 // Which property to store the id of the calendar of the event.
+//!                 // The default is "CALENDAR_ID".
+//!                 "calendar_property": "CALENDAR_ID",
 //!                 // Number of days to filter headline before today.
 //!                 // The default is 7.
 //!                 "up_days": 1,
 //!                 // Number of days to filter headline after today.
 //!                 // The default is 7.
-//!                 "down_days": 1
+//!                 "down_days": 1,
+//!                 // Which side wins when both the headline and the event were changed
+//!                 // since the last synchronization: "org", "calendar" or "ask".
+//!                 // This also applies to events changed in Google Calendar while
+//!                 // synchronizing, which are never overwritten blindly. Conflicts left
+//!                 // unanswered with "ask", e.g. when run by cron, are skipped.
+//!                 // The default is "org".
+//!                 "conflict": "ask",
+//!                 // What to do with a headline whose event was deleted from the calendar:
+//!                 // "remove", { "keyword": "CANCELLED" } or { "tag": "cancelled" }.
+//!                 // The default is { "keyword": "DONE" }.
+//!                 "on_delete": "remove",
+//!                 // Maximum number of deletions in one run. If exceeded, deletions
+//!                 // are skipped in both directions.
+//!                 // The default is 10.
+//!                 "max_deletions": 10,
+//!                 // Who is notified when events are created, changed or deleted:
+//!                 // "all", "externalOnly" for attendees without Google Calendar,
+//!                 // or "none".
+//!                 // The default is "none".
+//!                 "send_updates": "all",
+//!                 // Time zone of org timestamps in this file, overriding the global one.
+//!                 "time_zone": "Asia/Tokyo",
+//!                 // How headline fields are mapped to event fields.
+//!                 "fields": {
+//!                     // Whether to sync the section of the headline, without drawers,
+//!                     // with the event description.
+//!                     // The default is true.
+//!                     "description": true,
+//!                     // Which property to sync with the event location, or null to skip.
+//!                     // The default is "LOCATION".
+//!                     "location": "LOCATION",
+//!                     // Where to store headline tags: "none", "description" or "extended"
+//!                     // for a private extended property.
+//!                     // The default is "extended".
+//!                     "tags": "description",
+//!                     // Which property to sync with the event reminders, or null to skip.
+//!                     // Reminders are listed like "popup 10m, email 1d", the method
+//!                     // defaulting to popup. The warning period of a deadline, like
+//!                     // "-3d", is synced as a popup reminder as well. Reminders more
+//!                     // than four weeks before the event are ignored.
+//!                     // The default is "REMINDERS".
+//!                     "reminders": "ALARM",
+//!                     // Which property to sync with the event attendees, or null to skip.
+//!                     // Attendees are listed by email, like "alice@example.com, bob@example.com",
+//!                     // and their response status is written back, like
+//!                     // "alice@example.com (accepted)".
+//!                     // The default is "ATTENDEES".
+//!                     "attendees": "ATTENDEES"
+//!                 },
+//!                 // Event colors of tags and priorities, as listed by "google colors".
+//!                 // Tags take precedence over priorities. The default is empty.
+//!                 "colors": {
+//!                     "tags": { "work": "9" },
+//!                     "priorities": { "A": "11" }
+//!                 }
 //!             }
 //!         }
 //!     ]
@@ -196,7 +358,8 @@ mod logger;
 #[cfg(feature = "toggl")]
 mod toggl;
 
-use log::LevelFilter;
+use async_std::task;
+use log::{error, LevelFilter};
 use std::io::stdout;
 use std::path::PathBuf;
 use std::process;
@@ -251,9 +414,73 @@ enum Cmd {
         #[structopt(short, long, parse(from_os_str))]
         conf_path: Option<PathBuf>,
     },
+    /// Authorizes access to applications
+    #[cfg(feature = "google_calendar")]
+    #[structopt(name = "auth")]
+    Auth {
+        #[structopt(subcommand)]
+        subcommand: AuthCmd,
+    },
+    /// Inspects Google Calendar
+    #[cfg(feature = "google_calendar")]
+    #[structopt(name = "google")]
+    Google {
+        #[structopt(subcommand)]
+        subcommand: GoogleCmd,
+    },
+}
+
+#[cfg(feature = "google_calendar")]
+#[derive(StructOpt, Debug)]
+enum AuthCmd {
+    /// Authorizes access to Google Calendar
+    #[structopt(name = "google")]
+    Google {
+        /// Pastes the authorization code instead of receiving it on localhost
+        #[structopt(long = "no-browser")]
+        no_browser: bool,
+        /// Increases verbosity
+        #[structopt(short, long)]
+        verbose: bool,
+        /// Path to configuration file
+        #[structopt(short, long, parse(from_os_str))]
+        conf_path: Option<PathBuf>,
+    },
+}
+
+#[cfg(feature = "google_calendar")]
+#[derive(StructOpt, Debug)]
+enum GoogleCmd {
+    /// Lists the calendars of the user
+    #[structopt(name = "calendars")]
+    Calendars {
+        /// Increases verbosity
+        #[structopt(short, long)]
+        verbose: bool,
+        /// Path to configuration file
+        #[structopt(short, long, parse(from_os_str))]
+        conf_path: Option<PathBuf>,
+    },
+    /// Lists the palette of event colors
+    #[structopt(name = "colors")]
+    Colors {
+        /// Increases verbosity
+        #[structopt(short, long)]
+        verbose: bool,
+        /// Path to configuration file
+        #[structopt(short, long, parse(from_os_str))]
+        conf_path: Option<PathBuf>,
+    },
+}
+
+fn main() {
+    if let Err(err) = run() {
+        error!("{}", err);
+        process::exit(1);
+    }
 }
 
-fn main() -> Result<()> {
+fn run() -> Result<()> {
     match Opt::from_args().subcommand {
         Cmd::Init { verbose } => {
             init_logger(verbose);
@@ -276,22 +503,89 @@ fn main() -> Result<()> {
         Cmd::Sync {
             verbose,
             conf_path,
+            #[cfg(feature = "google_calendar")]
             skip_google_calendar,
+            #[cfg(feature = "toggl")]
             skip_toggl,
         } => {
             init_logger(verbose);
 
+            #[cfg(feature = "google_calendar")]
+            let conf = Conf::new(conf_path)?;
+            // still checks the configuration and loads the .env file
+            #[cfg(not(feature = "google_calendar"))]
             let _conf = Conf::new(conf_path)?;
 
-            if cfg!(feature = "google_calendar") && !skip_google_calendar {}
+            task::block_on(async {
+                #[cfg(feature = "google_calendar")]
+                {
+                    if !skip_google_calendar {
+                        google::sync(&conf).await?;
+                    }
+                }
+
+                #[cfg(feature = "toggl")]
+                {
+                    if !skip_toggl {
+                        toggl::sync().await;
+                    }
+                }
+
+                Ok::<_, error::Error>(())
+            })?;
+        }
+        #[cfg(feature = "google_calendar")]
+        Cmd::Auth {
+            subcommand:
+                AuthCmd::Google {
+                    no_browser,
+                    verbose,
+                    conf_path,
+                },
+        } => {
+            init_logger(verbose);
+
+            let conf = Conf::new(conf_path)?;
+
+            task::block_on(google::auth::authorize(
+                google_calendar_conf(&conf),
+                no_browser,
+            ))?;
+        }
+        #[cfg(feature = "google_calendar")]
+        Cmd::Google {
+            subcommand: GoogleCmd::Calendars { verbose, conf_path },
+        } => {
+            init_logger(verbose);
+
+            let conf = Conf::new(conf_path)?;
+
+            task::block_on(google::print_calendars(google_calendar_conf(&conf)))?;
+        }
+        #[cfg(feature = "google_calendar")]
+        Cmd::Google {
+            subcommand: GoogleCmd::Colors { verbose, conf_path },
+        } => {
+            init_logger(verbose);
+
+            let conf = Conf::new(conf_path)?;
 
-            if cfg!(feature = "toggl") && !skip_toggl {}
+            task::block_on(google::print_colors(google_calendar_conf(&conf)))?;
         }
     }
 
     Ok(())
 }
 
+/// Returns the global Google Calendar configuration, or exits if missing
+#[cfg(feature = "google_calendar")]
+fn google_calendar_conf(conf: &Conf) -> &conf::GoogleCalendarGlobalConf {
+    conf.google_calendar.as_ref().unwrap_or_else(|| {
+        error!("Google Calendar is not configured.");
+        process::exit(1);
+    })
+}
+
 fn init_logger(verbose: bool) {
     log::set_logger(&logger::LOGGER).unwrap();
     if verbose {
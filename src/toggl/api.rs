@@ -0,0 +1,198 @@
+use chrono::{DateTime, SecondsFormat, Utc};
+use isahc::http::{Method, StatusCode};
+use isahc::prelude::{Request, RequestExt, Response, ResponseExt};
+use isahc::Body;
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use serde::de::DeserializeOwned;
+
+use crate::{
+    conf::TogglGlobalConf,
+    error::{Error, Result},
+    toggl::models::{Project, Tag, TimeEntry, Workspace},
+};
+
+const API_URL: &str = "https://api.track.toggl.com/api/v9";
+
+/// Application name reported when creating time entries
+const CREATED_WITH: &str = "orgize-sync";
+
+/// Toggl API client, authenticated with an API token
+pub struct Client {
+    authorization: String,
+}
+
+impl Client {
+    pub fn new(conf: &TogglGlobalConf) -> Self {
+        // the password of API tokens is literally "api_token"
+        let credentials = format!("{}:api_token", conf.api_token);
+
+        Client {
+            authorization: format!("Basic {}", base64::encode(&credentials)),
+        }
+    }
+
+    /// Sends a request with a JSON body, if any.
+    pub async fn send(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<Vec<u8>>,
+    ) -> Result<Response<Body>> {
+        let mut builder = Request::builder();
+
+        builder
+            .method(method)
+            .uri(format!("{}{}", API_URL, path))
+            .header("authorization", &self.authorization);
+
+        let req = match body {
+            Some(body) => builder
+                .header("content-type", "application/json")
+                .body(Body::from(body))?,
+            None => builder.body(Body::empty())?,
+        };
+
+        check(req.send_async().await?)
+    }
+}
+
+/// Returns the response if successful, or the error it contains
+fn check(mut res: Response<Body>) -> Result<Response<Body>> {
+    let status = res.status();
+
+    if status.is_success() {
+        return Ok(res);
+    }
+
+    let text = res.text()?;
+
+    // messages are sent either as plain text or as JSON strings
+    let message = serde_json::from_str::<String>(&text).unwrap_or(text);
+
+    Err(Error::TogglApi {
+        status,
+        message: message.trim().into(),
+    })
+}
+
+fn parse<T: DeserializeOwned>(mut res: Response<Body>) -> Result<T> {
+    Ok(res.json()?)
+}
+
+/// Lists the workspaces of the user
+pub async fn list_workspaces(client: &Client) -> Result<Vec<Workspace>> {
+    parse(client.send(Method::GET, "/me/workspaces", None).await?)
+}
+
+pub async fn list_projects(client: &Client, workspace_id: u64) -> Result<Vec<Project>> {
+    let path = format!("/workspaces/{}/projects", workspace_id);
+
+    parse(client.send(Method::GET, &path, None).await?)
+}
+
+pub async fn list_tags(client: &Client, workspace_id: u64) -> Result<Vec<Tag>> {
+    let path = format!("/workspaces/{}/tags", workspace_id);
+
+    parse(client.send(Method::GET, &path, None).await?)
+}
+
+/// Lists the time entries of the user started between `start` and `end`
+pub async fn list_time_entries(
+    client: &Client,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+) -> Result<Vec<TimeEntry>> {
+    let path = format!(
+        "/me/time_entries?start_date={}&end_date={}",
+        utf8_percent_encode(
+            &start.to_rfc3339_opts(SecondsFormat::Secs, true),
+            NON_ALPHANUMERIC
+        ),
+        utf8_percent_encode(
+            &end.to_rfc3339_opts(SecondsFormat::Secs, true),
+            NON_ALPHANUMERIC
+        ),
+    );
+
+    parse(client.send(Method::GET, &path, None).await?)
+}
+
+/// Creates a time entry in its workspace
+pub async fn create_time_entry(client: &Client, entry: &TimeEntry) -> Result<TimeEntry> {
+    let path = format!("/workspaces/{}/time_entries", entry.workspace_id);
+
+    let entry = TimeEntry {
+        created_with: CREATED_WITH.into(),
+        ..entry.clone()
+    };
+
+    let res = client
+        .send(Method::POST, &path, Some(serde_json::to_vec(&entry)?))
+        .await?;
+
+    parse(res)
+}
+
+/// Replaces the fields of a time entry
+pub async fn update_time_entry(client: &Client, id: u64, entry: &TimeEntry) -> Result<TimeEntry> {
+    let path = format!("/workspaces/{}/time_entries/{}", entry.workspace_id, id);
+
+    let res = client
+        .send(Method::PUT, &path, Some(serde_json::to_vec(entry)?))
+        .await?;
+
+    parse(res)
+}
+
+pub async fn delete_time_entry(client: &Client, workspace_id: u64, id: u64) -> Result<()> {
+    let path = format!("/workspaces/{}/time_entries/{}", workspace_id, id);
+
+    match client.send(Method::DELETE, &path, None).await {
+        // already deleted
+        Err(Error::TogglApi { status, .. }) if status == StatusCode::NOT_FOUND => Ok(()),
+        res => res.map(|_| ()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(status: u16, body: &str) -> Response<Body> {
+        Response::builder()
+            .status(status)
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    fn error(res: Response<Body>) -> (StatusCode, String) {
+        match check(res) {
+            Err(Error::TogglApi { status, message }) => (status, message),
+            _ => panic!("expected an error response"),
+        }
+    }
+
+    #[test]
+    fn check_success() {
+        assert!(check(response(200, "[]")).is_ok());
+    }
+
+    #[test]
+    fn check_plain_text() {
+        assert_eq!(
+            error(response(403, "Incorrect username and/or password\n")),
+            (
+                StatusCode::FORBIDDEN,
+                "Incorrect username and/or password".into()
+            )
+        );
+    }
+
+    #[test]
+    fn check_json_string() {
+        assert_eq!(
+            error(response(400, "\"Invalid project_id\"\n")),
+            (StatusCode::BAD_REQUEST, "Invalid project_id".into())
+        );
+    }
+}
@@ -1,3 +1,7 @@
+// the client is only used by the synchronization of time entries, which is
+// not implemented yet
+#![allow(dead_code)]
+
 pub mod api;
 pub mod models;
 
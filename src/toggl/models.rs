@@ -0,0 +1,151 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Deserialize)]
+pub struct Workspace {
+    /// Identifier of the workspace.
+    pub id: u64,
+    /// Name of the workspace.
+    pub name: String,
+    /// Identifier of the organization owning the workspace.
+    pub organization_id: u64,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct Project {
+    /// Identifier of the project.
+    pub id: u64,
+    /// Identifier of the workspace of the project.
+    pub workspace_id: u64,
+    /// Identifier of the client of the project, if any.
+    pub client_id: Option<u64>,
+    /// Name of the project.
+    pub name: String,
+    /// Whether the project is active or archived.
+    pub active: bool,
+    /// Color of the project, as a hex code like "#06aaf5".
+    pub color: String,
+    /// Whether the project is billable. Premium feature.
+    pub billable: Option<bool>,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct Tag {
+    /// Identifier of the tag.
+    pub id: u64,
+    /// Identifier of the workspace of the tag.
+    pub workspace_id: u64,
+    /// Name of the tag.
+    pub name: String,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TimeEntry {
+    /// Identifier of the time entry. Read-only.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<u64>,
+    /// Identifier of the workspace of the time entry.
+    pub workspace_id: u64,
+    /// Identifier of the project of the time entry, if any.
+    pub project_id: Option<u64>,
+    /// Identifier of the task of the time entry, if any.
+    pub task_id: Option<u64>,
+    /// Description of the time entry.
+    pub description: Option<String>,
+    /// Names of the tags of the time entry. Tags which don't exist are
+    /// created.
+    pub tags: Option<Vec<String>>,
+    /// Whether the time entry is billable.
+    pub billable: bool,
+    /// Start time of the time entry.
+    pub start: DateTime<Utc>,
+    /// Stop time of the time entry, or `None` while it is running.
+    pub stop: Option<DateTime<Utc>>,
+    /// Duration of the time entry in seconds, or a negative value while
+    /// it is running.
+    pub duration: i64,
+    /// Name of the application which created the time entry. Required when
+    /// creating time entries.
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub created_with: String,
+    /// Last modification time of the time entry. Read-only.
+    #[serde(skip_serializing)]
+    pub at: Option<DateTime<Utc>>,
+}
+
+impl TimeEntry {
+    /// Returns `true` if this time entry is still running.
+    pub fn is_running(&self) -> bool {
+        self.duration < 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn serialize_time_entry() {
+        let entry = TimeEntry {
+            id: Some(42),
+            workspace_id: 7,
+            description: Some("Review".into()),
+            start: "2026-10-01T09:00:00Z".parse().unwrap(),
+            stop: None,
+            duration: -1,
+            at: Some("2026-10-01T09:00:05Z".parse().unwrap()),
+            ..Default::default()
+        };
+
+        // read-only fields are not sent
+        assert_eq!(
+            serde_json::to_value(&entry).unwrap(),
+            json!({
+                "id": 42,
+                "workspace_id": 7,
+                "project_id": null,
+                "task_id": null,
+                "description": "Review",
+                "tags": null,
+                "billable": false,
+                "start": "2026-10-01T09:00:00Z",
+                "stop": null,
+                "duration": -1,
+            })
+        );
+        assert!(entry.is_running());
+    }
+
+    #[test]
+    fn serialize_new_time_entry() {
+        let entry = TimeEntry {
+            created_with: "orgize-sync".into(),
+            duration: 3600,
+            ..Default::default()
+        };
+
+        let value = serde_json::to_value(&entry).unwrap();
+
+        assert!(value.get("id").is_none());
+        assert_eq!(value["created_with"], "orgize-sync");
+        assert!(!entry.is_running());
+    }
+
+    #[test]
+    fn deserialize_time_entry() {
+        let entry: TimeEntry = serde_json::from_str(
+            r#"{"id":42,"workspace_id":7,"project_id":null,"description":"Review",
+                "tags":["org"],"billable":false,"start":"2026-10-01T09:00:00+00:00",
+                "stop":"2026-10-01T10:00:00+00:00","duration":3600,
+                "at":"2026-10-01T10:00:05+00:00","user_id":3}"#,
+        )
+        .unwrap();
+
+        assert_eq!(entry.id, Some(42));
+        assert_eq!(entry.tags, Some(vec!["org".into()]));
+        assert_eq!(entry.duration, 3600);
+        assert!(entry.at.is_some());
+    }
+}